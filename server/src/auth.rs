@@ -5,75 +5,478 @@ use bcrypt::{hash, verify, DEFAULT_COST};
 use anyhow::Result;
 use axum::{
     async_trait,
-    extract::{FromRequestParts, TypedHeader},
-    headers::{Authorization, authorization::Bearer},
-    http::request::Parts,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
     RequestPartsExt,
 };
+use axum_extra::{
+    headers::{Authorization, authorization::Bearer},
+    TypedHeader,
+};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
 use std::env;
+use std::fmt;
+
+use crate::database::{ApiKey, Database, User};
+use crate::error::ApiError;
+
+/// Access tokens are short-lived; clients keep sessions going with a refresh token.
+pub const ACCESS_TOKEN_TTL: chrono::Duration = chrono::Duration::minutes(15);
+const REFRESH_TOKEN_TTL: chrono::Duration = chrono::Duration::days(30);
+pub const EMAIL_VERIFICATION_TTL: chrono::Duration = chrono::Duration::hours(24);
+pub const PASSWORD_RESET_TTL: chrono::Duration = chrono::Duration::hours(1);
+/// Lets the extractor tell API keys from JWTs, and secret scanners spot leaked keys.
+const API_KEY_PREFIX: &str = "kg_";
+/// How much of a key is kept in clear so users can recognise it.
+const API_KEY_DISPLAY_LENGTH: usize = 11;
+const MAX_API_KEY_NAME_LENGTH: usize = 100;
+
+pub const ROLE_USER: &str = "user";
+pub const ROLE_ADMIN: &str = "admin";
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// bcrypt ignores everything past 72 bytes, so longer passwords would be silently cut.
+const MAX_PASSWORD_BYTES: usize = 72;
+const MAX_EMAIL_LENGTH: usize = 254;
+/// Costs bcrypt accepts. Each step doubles the time a hash takes.
+const MIN_BCRYPT_COST: u32 = 4;
+const MAX_BCRYPT_COST: u32 = 31;
+const DEFAULT_ACCOUNT_LOCK_THRESHOLD: u32 = 10;
+const DEFAULT_ACCOUNT_LOCK_SECS: i64 = 15 * 60;
+/// Each further failure doubles the lock, up to this.
+const MAX_ACCOUNT_LOCK: chrono::Duration = chrono::Duration::days(1);
+
+/// A problem with what the user submitted; the message is safe to show them.
+#[derive(Debug)]
+pub struct AuthInputError(pub String);
+
+impl fmt::Display for AuthInputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for AuthInputError {}
+
+/// A wrong password, at login or when confirming an account change.
+#[derive(Debug)]
+pub struct IncorrectPassword;
+
+impl fmt::Display for IncorrectPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Password is incorrect")
+    }
+}
+
+impl std::error::Error for IncorrectPassword {}
+
+/// The account is locked after too many wrong passwords, until `until`.
+#[derive(Debug)]
+pub struct AccountLocked {
+    pub until: chrono::DateTime<chrono::Utc>,
+}
+
+impl fmt::Display for AccountLocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Account locked until {}", self.until.to_rfc3339())
+    }
+}
+
+impl std::error::Error for AccountLocked {}
+
+/// Requires a minimum length and at least three of lowercase, uppercase, digits and symbols.
+pub fn validate_password_strength(password: &str) -> std::result::Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!("Password must be at least {} characters long", MIN_PASSWORD_LENGTH));
+    }
+    if password.len() > MAX_PASSWORD_BYTES {
+        return Err(format!("Password must be at most {} bytes long", MAX_PASSWORD_BYTES));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|&&present| present).count() < 3 {
+        return Err("Password must mix at least three of: lowercase letters, uppercase letters, digits and symbols".to_string());
+    }
+
+    Ok(())
+}
 
-use crate::database::Database;
+/// Cost of new password hashes: `BCRYPT_COST`, or bcrypt's default of 12.
+pub fn bcrypt_cost() -> u32 {
+    env::var("BCRYPT_COST")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|cost| (MIN_BCRYPT_COST..=MAX_BCRYPT_COST).contains(cost))
+        .unwrap_or(DEFAULT_COST)
+}
+
+/// How long an account is locked after its `failed_attempts`th wrong password in a
+/// row, if at all: `ACCOUNT_LOCK_SECS` once `ACCOUNT_LOCK_THRESHOLD` is reached,
+/// doubling with every failure after that.
+pub fn account_lock_duration(failed_attempts: u32) -> Option<chrono::Duration> {
+    let var = |key: &str| env::var(key).ok().and_then(|value| value.parse::<u32>().ok()).filter(|&n| n > 0);
+    let threshold = var("ACCOUNT_LOCK_THRESHOLD").unwrap_or(DEFAULT_ACCOUNT_LOCK_THRESHOLD);
+    let base = var("ACCOUNT_LOCK_SECS").map_or(DEFAULT_ACCOUNT_LOCK_SECS, i64::from);
+
+    let doublings = failed_attempts.checked_sub(threshold)?;
+    let seconds = base.saturating_mul(1i64.checked_shl(doublings.min(62)).unwrap_or(i64::MAX));
+    Some(chrono::Duration::seconds(seconds.min(MAX_ACCOUNT_LOCK.num_seconds())))
+}
+
+/// Checks `password` against `user`'s, counting wrong ones towards a lock: fails with
+/// [`AccountLocked`] while the account is locked and [`IncorrectPassword`] otherwise.
+async fn check_password(db: &Database, user: &User, password: &str) -> Result<()> {
+    let now = chrono::Utc::now();
+    if let Some(until) = user.locked_until.filter(|&until| until > now) {
+        return Err(AccountLocked { until }.into());
+    }
+
+    if !verify(password, &user.password_hash)? {
+        let failed_attempts = db.record_failed_login(&user.id).await?;
+        if let Some(duration) = account_lock_duration(failed_attempts) {
+            let until = now + duration;
+            db.lock_user(&user.id, until).await?;
+            tracing::warn!("Locked user {} after {} wrong passwords, until {}", user.id, failed_attempts, until);
+            return Err(AccountLocked { until }.into());
+        }
+        return Err(IncorrectPassword.into());
+    }
+    if user.failed_attempts > 0 || user.locked_until.is_some() {
+        db.reset_failed_logins(&user.id).await?;
+    }
+    Ok(())
+}
+
+/// Re-hashes `user`'s (already checked) `password` at `cost` when their stored hash was
+/// made with a lower one. Returns whether it did.
+async fn upgrade_password_hash(db: &Database, user: &User, password: &str, cost: u32) -> Result<bool> {
+    if get_hash_cost(&user.password_hash).is_none_or(|current| current >= cost) {
+        return Ok(false);
+    }
+    db.update_password_hash(&user.id, &hash(password, cost)?).await?;
+    Ok(true)
+}
+
+/// The cost a bcrypt hash (`$2b$12$...`) was made with.
+pub fn get_hash_cost(password_hash: &str) -> Option<u32> {
+    password_hash.split('$').nth(2)?.parse().ok()
+}
+
+/// Trims and lowercases the address, then checks it looks like `local@domain.tld`.
+pub fn normalize_email(email: &str) -> std::result::Result<String, String> {
+    let email = email.trim().to_lowercase();
+    let valid = email.len() <= MAX_EMAIL_LENGTH
+        && !email.chars().any(char::is_whitespace)
+        && match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.split('.').count() >= 2
+                    && domain.split('.').all(|label| !label.is_empty())
+            }
+            None => false,
+        };
+
+    if valid {
+        Ok(email)
+    } else {
+        Err("Email address is not valid".to_string())
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub email: String,
     pub exp: usize,
+    /// Issue time, checked against the user's `tokens_valid_after`. Missing from tokens
+    /// issued before it was added, which then only last until the next password change.
+    #[serde(default)]
+    pub iat: usize,
+    /// Unique per token, so a single token can be revoked on logout.
+    pub jti: String,
+    /// Tokens issued before roles existed carry none and get no admin rights.
+    #[serde(default)]
+    pub role: String,
 }
 
+impl Claims {
+    pub fn is_admin(&self) -> bool {
+        self.role == ROLE_ADMIN
+    }
+}
+
+/// What a successful sign-in or refresh hands back to the client.
+pub struct Session {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub user_id: String,
+    pub email_verified: bool,
+}
+
+#[derive(Clone)]
 pub struct AuthService {
     encoding_key: EncodingKey,
     decoding_key: DecodingKey,
+    /// Whether new and changed addresses need verifying. Without SMTP there's no way to
+    /// deliver a verification email.
+    verify_emails: bool,
 }
 
+/// Signs tokens when `JWT_SECRET` isn't set. Generated once per process, so tokens still
+/// verify across `AuthService`s but every session ends when the server restarts.
+static FALLBACK_JWT_SECRET: Lazy<String> = Lazy::new(|| {
+    tracing::warn!("JWT_SECRET is not set, signing tokens with a random key; sessions won't survive a restart");
+    format!("{}{}", generate_refresh_token(), generate_refresh_token())
+});
+
 impl AuthService {
     pub fn new() -> Self {
-        let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "keyguard-secret-key".to_string());
+        let secret = env::var("JWT_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty())
+            .unwrap_or_else(|| FALLBACK_JWT_SECRET.clone());
         Self {
             encoding_key: EncodingKey::from_secret(secret.as_ref()),
             decoding_key: DecodingKey::from_secret(secret.as_ref()),
+            verify_emails: crate::email::is_configured(),
         }
     }
 
-    pub async fn register(&self, db: &Database, email: &str, password: &str) -> Result<(String, String)> {
+    pub async fn register(&self, db: &Database, email: &str, password: &str) -> Result<Session> {
+        let email = normalize_email(email).map_err(AuthInputError)?;
+        validate_password_strength(password).map_err(AuthInputError)?;
+
         // Check if user already exists
-        if db.get_user_by_email(email).await?.is_some() {
-            return Err(anyhow::anyhow!("User already exists"));
+        if db.get_user_by_email(&email).await?.is_some() {
+            return Err(AuthInputError("User already exists".to_string()).into());
         }
 
         // Hash password
-        let password_hash = hash(password, DEFAULT_COST)?;
+        let password_hash = hash(password, bcrypt_cost())?;
 
-        // Create user
-        let user_id = db.create_user(email, &password_hash).await?;
+        // Create user; the configured admin email becomes an admin when it signs up
+        let role = if admin_email().as_deref() == Some(email.as_str()) { ROLE_ADMIN } else { ROLE_USER };
+        let email_verified = role == ROLE_ADMIN || !self.verify_emails;
+        let user_id = db.create_user(&email, &password_hash, role, email_verified).await?;
 
-        // Generate token
-        let token = self.generate_token(&user_id, email)?;
+        db.record_audit_event(&user_id, "registered").await?;
+        if !email_verified {
+            send_email_verification(db, &user_id, &email).await?;
+        }
 
-        Ok((token, user_id))
+        self.start_session(db, &user_id, &email, role, email_verified).await
     }
 
-    pub async fn login(&self, db: &Database, email: &str, password: &str) -> Result<(String, String)> {
+    /// Moves the account to `new_email`, which then needs verifying like a new sign-up,
+    /// and/or sets `new_password`, which signs out every session as a reset does. Both
+    /// are checked before either is written, so a rejected request changes nothing.
+    pub async fn update_account(
+        &self,
+        db: &Database,
+        user_id: &str,
+        current_password: &str,
+        new_email: Option<&str>,
+        new_password: Option<&str>,
+    ) -> Result<()> {
+        let user = self.confirm_password(db, user_id, current_password).await?;
+
+        let email = new_email
+            .map(normalize_email)
+            .transpose()
+            .map_err(AuthInputError)?
+            .filter(|email| *email != user.email);
+        if let Some(email) = &email {
+            if db.get_user_by_email(email).await?.is_some() {
+                return Err(AuthInputError("Email address is already in use".to_string()).into());
+            }
+        }
+        let password_hash = match new_password {
+            Some(password) => {
+                validate_password_strength(password).map_err(AuthInputError)?;
+                Some(hash(password, bcrypt_cost())?)
+            }
+            None => None,
+        };
+
+        if let Some(email) = &email {
+            db.update_user_email(user_id, email, !self.verify_emails).await?;
+            db.record_audit_event(user_id, "email_changed").await?;
+            if self.verify_emails {
+                send_email_verification(db, user_id, email).await?;
+            }
+        }
+        if let Some(password_hash) = password_hash {
+            db.reset_password(user_id, &password_hash).await?;
+            db.record_audit_event(user_id, "password_changed").await?;
+        }
+        Ok(())
+    }
+
+    async fn confirm_password(&self, db: &Database, user_id: &str, password: &str) -> Result<User> {
+        let user = db.get_user_by_id(user_id).await?
+            .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+        check_password(db, &user, password).await?;
+        Ok(user)
+    }
+
+    /// Confirms the address of the user the token was emailed to. Tokens work once.
+    pub async fn verify_email(&self, db: &Database, token: &str) -> Result<()> {
+        let Some((user_id, expires_at)) = db.take_email_verification(&hash_token(token.trim())).await? else {
+            return Err(AuthInputError("Invalid verification token".to_string()).into());
+        };
+        if expires_at < chrono::Utc::now().timestamp() {
+            return Err(AuthInputError("Verification token expired".to_string()).into());
+        }
+
+        db.set_email_verified(&user_id).await?;
+        db.record_audit_event(&user_id, "email_verified").await
+    }
+
+    /// Emails a reset token when `email` belongs to an account. Says nothing either way,
+    /// so callers can't use it to find out who has one; the email goes out in the
+    /// background for the same reason.
+    pub async fn request_password_reset(&self, db: &Database, email: &str) -> Result<()> {
+        let Some(user) = db.get_user_by_email(email.trim()).await? else {
+            return Ok(());
+        };
+
+        let token = generate_refresh_token();
+        let expires_at = (chrono::Utc::now() + PASSWORD_RESET_TTL).timestamp();
+        db.create_password_reset(&hash_token(&token), &user.id, expires_at).await?;
+        tokio::spawn(async move {
+            if let Err(e) = crate::email::send_password_reset_email(&user.email, &token).await {
+                tracing::warn!("Failed to send password reset email to user {}: {}", user.id, e);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Sets a new password with a token from `request_password_reset`, signing the
+    /// user out everywhere. Tokens work once.
+    pub async fn reset_password(&self, db: &Database, token: &str, new_password: &str) -> Result<()> {
+        // Checked first, so a weak password doesn't use up the token
+        validate_password_strength(new_password).map_err(AuthInputError)?;
+
+        let Some((user_id, expires_at)) = db.take_password_reset(&hash_token(token.trim())).await? else {
+            return Err(AuthInputError("Invalid reset token".to_string()).into());
+        };
+        if expires_at < chrono::Utc::now().timestamp() {
+            return Err(AuthInputError("Reset token expired".to_string()).into());
+        }
+
+        let password_hash = hash(new_password, bcrypt_cost())?;
+        db.reset_password(&user_id, &password_hash).await?;
+        db.record_audit_event(&user_id, "password_reset").await
+    }
+
+    pub async fn login(&self, db: &Database, email: &str, password: &str) -> Result<Session> {
         // Get user
-        let user = db.get_user_by_email(email).await?
+        let user = db.get_user_by_email(email.trim()).await?
             .ok_or_else(|| anyhow::anyhow!("Invalid credentials"))?;
 
-        // Verify password
-        if !verify(password, &user.password_hash)? {
-            return Err(anyhow::anyhow!("Invalid credentials"));
+        check_password(db, &user, password).await?;
+
+        // The password is only ever at hand here, so this is when old hashes get upgraded
+        if let Err(e) = upgrade_password_hash(db, &user, password, bcrypt_cost()).await {
+            tracing::warn!("Failed to upgrade password hash of user {}: {}", user.id, e);
         }
 
-        // Generate token
-        let token = self.generate_token(&user.id, email)?;
+        db.record_audit_event(&user.id, "logged_in").await?;
+        self.start_session(db, &user.id, &user.email, &user.role, user.email_verified).await
+    }
+
+    /// Trades a refresh token for a new access token and a new refresh token. Presenting
+    /// a token that was already rotated means it leaked, so its whole family is revoked.
+    pub async fn refresh(&self, db: &Database, refresh_token: &str) -> Result<Session> {
+        let stored = db.get_refresh_token(&hash_token(refresh_token)).await?
+            .ok_or_else(|| anyhow::anyhow!("Invalid refresh token"))?;
+
+        if stored.expires_at < chrono::Utc::now().timestamp() {
+            return Err(anyhow::anyhow!("Refresh token expired"));
+        }
 
-        Ok((token, user.id))
+        let new_token = generate_refresh_token();
+        let expires_at = (chrono::Utc::now() + REFRESH_TOKEN_TTL).timestamp();
+        let rotated = stored.used_at.is_none()
+            && db.rotate_refresh_token(&stored.token_hash, &hash_token(&new_token), expires_at).await?;
+        if !rotated {
+            tracing::warn!("Refresh token reuse detected for user {}, revoking its session", stored.user_id);
+            db.revoke_refresh_token_family(&stored.family_id).await?;
+            return Err(anyhow::anyhow!("Refresh token reuse detected"));
+        }
+
+        let user = db.get_user_by_id(&stored.user_id).await?
+            .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+
+        Ok(Session {
+            access_token: self.generate_token(&user.id, &user.email, &user.role)?,
+            refresh_token: new_token,
+            user_id: user.id,
+            email_verified: user.email_verified,
+        })
     }
 
-    fn generate_token(&self, user_id: &str, email: &str) -> Result<String> {
+    /// Ends the session the refresh token belongs to. Unknown tokens are ignored.
+    pub async fn revoke_refresh_token(&self, db: &Database, refresh_token: &str) -> Result<()> {
+        if let Some(stored) = db.get_refresh_token(&hash_token(refresh_token)).await? {
+            db.revoke_refresh_token_family(&stored.family_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the key's metadata and the key itself, which is not stored and can't be
+    /// shown again.
+    pub async fn create_api_key(&self, db: &Database, user_id: &str, name: &str) -> Result<(ApiKey, String)> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_API_KEY_NAME_LENGTH {
+            return Err(AuthInputError(format!("Key name must be 1 to {} characters", MAX_API_KEY_NAME_LENGTH)).into());
+        }
+
+        let key = format!("{}{}", API_KEY_PREFIX, generate_refresh_token());
+        let metadata = db.create_api_key(user_id, name, &key[..API_KEY_DISPLAY_LENGTH], &hash_token(&key)).await?;
+        db.record_audit_event(user_id, "api_key_created").await?;
+
+        Ok((metadata, key))
+    }
+
+    async fn start_session(&self, db: &Database, user_id: &str, email: &str, role: &str, email_verified: bool) -> Result<Session> {
+        let refresh_token = generate_refresh_token();
+        let family_id = uuid::Uuid::new_v4().to_string();
+        let expires_at = (chrono::Utc::now() + REFRESH_TOKEN_TTL).timestamp();
+        db.create_refresh_token(&hash_token(&refresh_token), &family_id, user_id, expires_at).await?;
+
+        Ok(Session {
+            access_token: self.generate_token(user_id, email, role)?,
+            refresh_token,
+            user_id: user_id.to_string(),
+            email_verified,
+        })
+    }
+
+    pub async fn delete_account(&self, db: &Database, user_id: &str, password: &str) -> Result<()> {
+        // Require the current password so a stolen token alone can't erase an account
+        let user = self.confirm_password(db, user_id, password).await?;
+
+        db.delete_user(&user.id).await
+    }
+
+    fn generate_token(&self, user_id: &str, email: &str, role: &str) -> Result<String> {
+        let now = chrono::Utc::now();
         let claims = Claims {
             sub: user_id.to_string(),
             email: email.to_string(),
-            exp: (chrono::Utc::now() + chrono::Duration::days(30)).timestamp() as usize,
+            exp: (now + ACCESS_TOKEN_TTL).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            jti: uuid::Uuid::new_v4().to_string(),
+            role: role.to_string(),
         };
 
         let token = encode(&Header::default(), &claims, &self.encoding_key)?;
@@ -90,25 +493,470 @@ impl AuthService {
     }
 }
 
+/// `ADMIN_EMAIL`, normalized the same way as registration emails.
+pub fn admin_email() -> Option<String> {
+    env::var("ADMIN_EMAIL").ok().and_then(|email| normalize_email(&email).ok())
+}
+
+/// Signed-in users can only start scans once they've confirmed their email address.
+pub async fn require_verified(db: &Database, user_id: &str) -> std::result::Result<(), ApiError> {
+    let verified = db.is_email_verified(user_id).await
+        .map_err(|e| ApiError::internal("Failed to start scan", e))?;
+    if !verified {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "email_unverified",
+            "Verify your email address before starting scans",
+        ));
+    }
+    Ok(())
+}
+
+/// Stores a fresh verification token for the user and emails it to `email`. A lost
+/// email is only logged, since the account change has happened either way.
+async fn send_email_verification(db: &Database, user_id: &str, email: &str) -> Result<()> {
+    let token = generate_refresh_token();
+    let expires_at = (chrono::Utc::now() + EMAIL_VERIFICATION_TTL).timestamp();
+    db.create_email_verification(&hash_token(&token), user_id, expires_at).await?;
+    if let Err(e) = crate::email::send_verification_email(email, &token).await {
+        tracing::warn!("Failed to send verification email to user {}: {}", user_id, e);
+    }
+    Ok(())
+}
+
+/// 244 random bits from two v4 UUIDs, hex encoded. Also the random part of API keys.
+fn generate_refresh_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+/// Only this hash is stored, so a database leak doesn't hand out live sessions or keys.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
 #[async_trait]
 impl<S> FromRequestParts<S> for Claims
 where
     S: Send + Sync,
+    AuthService: FromRef<S>,
+    Database: FromRef<S>,
 {
-    type Rejection = axum::http::StatusCode;
+    type Rejection = StatusCode;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
-            .map_err(|_| axum::http::StatusCode::UNAUTHORIZED)?;
-
-        // Here you would normally validate the token
-        // For this example, we'll create a mock claims
-        Ok(Claims {
-            sub: "user123".to_string(),
-            email: "user@example.com".to_string(),
-            exp: (chrono::Utc::now() + chrono::Duration::days(30)).timestamp() as usize,
-        })
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        // Rejects bad signatures and expired tokens
+        let claims = AuthService::from_ref(state)
+            .verify_token(bearer.token())
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        let db = Database::from_ref(state);
+        match db.is_token_revoked(&claims.jti).await {
+            Ok(false) => {}
+            Ok(true) => return Err(StatusCode::UNAUTHORIZED),
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+
+        // Tokens outlive deleted accounts and password changes, so make sure the subject
+        // still exists and hasn't reset their password since
+        match db.get_user_by_id(&claims.sub).await {
+            Ok(Some(user)) if user.tokens_valid_after.is_some_and(|after| (claims.iat as i64) < after) => {
+                Err(StatusCode::UNAUTHORIZED)
+            }
+            Ok(Some(_)) => Ok(claims),
+            Ok(None) => Err(StatusCode::UNAUTHORIZED),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+}
+
+/// The user behind a request, authenticated with either a JWT or an API key sent as
+/// `Authorization: Bearer kg_...` or `X-API-Key: kg_...`.
+pub struct AuthUser {
+    pub user_id: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+    AuthService: FromRef<S>,
+    Database: FromRef<S>,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let api_key = match parts.headers.get("x-api-key") {
+            Some(value) => Some(value.to_str().map_err(|_| StatusCode::UNAUTHORIZED)?.to_string()),
+            None => parts
+                .headers
+                .get(axum::http::header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .filter(|token| token.starts_with(API_KEY_PREFIX))
+                .map(str::to_string),
+        };
+
+        let Some(api_key) = api_key else {
+            let claims = Claims::from_request_parts(parts, state).await?;
+            return Ok(Self { user_id: claims.sub });
+        };
+
+        match Database::from_ref(state).authenticate_api_key(&hash_token(&api_key)).await {
+            Ok(Some(user_id)) => Ok(Self { user_id }),
+            Ok(None) => Err(StatusCode::UNAUTHORIZED),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    const PASSWORD: &str = "Correct-Horse-42";
+
+    #[derive(Clone)]
+    struct TestState {
+        auth: AuthService,
+        db: Database,
+    }
+
+    impl FromRef<TestState> for AuthService {
+        fn from_ref(state: &TestState) -> Self {
+            state.auth.clone()
+        }
+    }
+
+    impl FromRef<TestState> for Database {
+        fn from_ref(state: &TestState) -> Self {
+            state.db.clone()
+        }
+    }
+
+    /// A fresh in-memory database with one registered user, signed in once.
+    async fn setup() -> (TestState, Session) {
+        // The lowest cost keeps hashing fast; every test sets the same value
+        env::set_var("BCRYPT_COST", MIN_BCRYPT_COST.to_string());
+        let state = TestState { auth: AuthService::new(), db: Database::open(":memory:").await.unwrap() };
+        let session = state.auth.register(&state.db, "user@example.com", PASSWORD).await.unwrap();
+        (state, session)
+    }
+
+    async fn authenticate(state: &TestState, token: &str) -> std::result::Result<Claims, StatusCode> {
+        let request = Request::builder()
+            .header("authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap();
+        Claims::from_request_parts(&mut request.into_parts().0, state).await
+    }
+
+    #[test]
+    fn passwords_need_length_and_variety() {
+        let too_short = validate_password_strength("Ab1!xyz").unwrap_err();
+        assert!(too_short.contains("at least 8 characters"), "{}", too_short);
+        assert!(validate_password_strength(&"Ab1!".repeat(20)).unwrap_err().contains("at most"));
+        for weak in ["alllowercase", "ALLUPPERCASE1", "12345678!"] {
+            assert!(validate_password_strength(weak).unwrap_err().contains("at least three"), "{}", weak);
+        }
+        for acceptable in [PASSWORD, "lower-and-1234", "Pässwort-über1"] {
+            assert_eq!(validate_password_strength(acceptable), Ok(()), "{}", acceptable);
+        }
+    }
+
+    #[test]
+    fn emails_are_normalized_and_checked() {
+        assert_eq!(normalize_email("  User@Example.COM ").as_deref(), Ok("user@example.com"));
+        for malformed in ["", "user", "user@", "@example.com", "user@example", "user@@example.com", "user@example..com", "us er@example.com"] {
+            assert!(normalize_email(malformed).is_err(), "{:?}", malformed);
+        }
+    }
+
+    #[tokio::test]
+    async fn registration_refuses_weak_passwords_and_bad_emails() {
+        let (state, _) = setup().await;
+        for (email, password) in [("new@example.com", "password"), ("not-an-email", PASSWORD)] {
+            let error = state.auth.register(&state.db, email, password).await.err().unwrap();
+            assert!(error.is::<AuthInputError>(), "{}", error);
+        }
+        assert!(state.db.get_user_by_email("new@example.com").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn revoked_tokens_are_refused_while_others_keep_working() {
+        let (state, first) = setup().await;
+        let second = state.auth.login(&state.db, "user@example.com", PASSWORD).await.unwrap();
+
+        let claims = authenticate(&state, &first.access_token).await.unwrap();
+        state.db.revoke_token(&claims.jti, &claims.sub, claims.exp as i64).await.unwrap();
+
+        assert_eq!(authenticate(&state, &first.access_token).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+        assert_eq!(authenticate(&state, &second.access_token).await.unwrap().sub, second.user_id);
+        assert_eq!(authenticate(&state, "not-a-jwt").await.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn only_unexpired_tokens_signed_with_our_key_are_accepted() {
+        let (state, session) = setup().await;
+        let claims = authenticate(&state, &session.access_token).await.unwrap();
+        assert_eq!(claims.sub, session.user_id);
+
+        let now = chrono::Utc::now().timestamp() as usize;
+
+        // Well past the default 60 s leeway
+        let expired = Claims { exp: now - 3600, iat: now - 7200, ..authenticate(&state, &session.access_token).await.unwrap() };
+        let expired = encode(&Header::default(), &expired, &state.auth.encoding_key).unwrap();
+        assert_eq!(authenticate(&state, &expired).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        // JWT_SECRET is never set in tests, so the old public fallback must not verify
+        let forged = Claims { role: ROLE_ADMIN.to_string(), ..authenticate(&state, &session.access_token).await.unwrap() };
+        let forged = encode(&Header::default(), &forged, &EncodingKey::from_secret(b"keyguard-secret-key")).unwrap();
+        assert_eq!(authenticate(&state, &forged).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        // Swapping the payload invalidates the signature, as does altering the signature
+        let [header, _, signature]: [&str; 3] = session.access_token.split('.').collect::<Vec<_>>().try_into().unwrap();
+        let payload = forged.split('.').nth(1).unwrap();
+        let tampered = format!("{}.{}.{}", header, payload, signature);
+        assert_eq!(authenticate(&state, &tampered).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+        let first = if signature.starts_with('A') { "B" } else { "A" };
+        let altered = format!("{}.{}{}", session.access_token.rsplit_once('.').unwrap().0, first, &signature[1..]);
+        assert_eq!(authenticate(&state, &altered).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        // Other instances in the same process share the random key
+        assert_eq!(AuthService::new().verify_token(&session.access_token).unwrap().sub, session.user_id);
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_the_token() {
+        let (state, session) = setup().await;
+
+        let refreshed = state.auth.refresh(&state.db, &session.refresh_token).await.unwrap();
+        assert_ne!(refreshed.refresh_token, session.refresh_token);
+        assert_eq!(authenticate(&state, &refreshed.access_token).await.unwrap().sub, session.user_id);
+
+        let again = state.auth.refresh(&state.db, &refreshed.refresh_token).await.unwrap();
+        assert_eq!(again.user_id, session.user_id);
+        assert!(state.auth.refresh(&state.db, "unknown").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reusing_a_rotated_token_ends_the_session() {
+        let (state, session) = setup().await;
+        let other = state.auth.login(&state.db, "user@example.com", PASSWORD).await.unwrap();
+        let refreshed = state.auth.refresh(&state.db, &session.refresh_token).await.unwrap();
+
+        // A replay means the token leaked, so its successor stops working too
+        assert!(state.auth.refresh(&state.db, &session.refresh_token).await.is_err());
+        assert!(state.auth.refresh(&state.db, &refreshed.refresh_token).await.is_err());
+        // Other sessions are a different family and carry on
+        assert!(state.auth.refresh(&state.db, &other.refresh_token).await.is_ok());
+    }
+
+    #[test]
+    fn lock_duration_grows_past_the_threshold() {
+        // ACCOUNT_LOCK_THRESHOLD and ACCOUNT_LOCK_SECS are never set in tests
+        let base = chrono::Duration::seconds(DEFAULT_ACCOUNT_LOCK_SECS);
+        assert_eq!(account_lock_duration(DEFAULT_ACCOUNT_LOCK_THRESHOLD - 1), None);
+        assert_eq!(account_lock_duration(DEFAULT_ACCOUNT_LOCK_THRESHOLD), Some(base));
+        assert_eq!(account_lock_duration(DEFAULT_ACCOUNT_LOCK_THRESHOLD + 1), Some(base * 2));
+        assert_eq!(account_lock_duration(u32::MAX), Some(MAX_ACCOUNT_LOCK));
+    }
+
+    #[tokio::test]
+    async fn wrong_passwords_lock_the_account_until_the_window_ends() {
+        let (state, session) = setup().await;
+        let login = |password: &'static str| state.auth.login(&state.db, "user@example.com", password);
+
+        for _ in 1..DEFAULT_ACCOUNT_LOCK_THRESHOLD {
+            assert!(login("wrong").await.err().unwrap().is::<IncorrectPassword>());
+        }
+        assert!(login("wrong").await.err().unwrap().is::<AccountLocked>());
+        // Locked even for the right password
+        assert!(login(PASSWORD).await.err().unwrap().is::<AccountLocked>());
+
+        let expired = chrono::Utc::now() - chrono::Duration::seconds(1);
+        state.db.lock_user(&session.user_id, expired).await.unwrap();
+        login(PASSWORD).await.unwrap();
+
+        let user = state.db.get_user_by_id(&session.user_id).await.unwrap().unwrap();
+        assert_eq!((user.failed_attempts, user.locked_until), (0, None));
+    }
+
+    #[tokio::test]
+    async fn wrong_current_passwords_count_towards_the_lock() {
+        let (state, session) = setup().await;
+
+        for _ in 1..DEFAULT_ACCOUNT_LOCK_THRESHOLD {
+            let error = state.auth.update_account(&state.db, &session.user_id, "wrong", Some("new@example.com"), None).await.unwrap_err();
+            assert!(error.is::<IncorrectPassword>());
+        }
+        let error = state.auth.update_account(&state.db, &session.user_id, "wrong", None, Some("New-Password-99")).await.unwrap_err();
+        assert!(error.is::<AccountLocked>());
+        assert!(state.auth.login(&state.db, "user@example.com", PASSWORD).await.err().unwrap().is::<AccountLocked>());
+    }
+
+    #[tokio::test]
+    async fn password_changes_refuse_earlier_access_tokens() {
+        let (state, session) = setup().await;
+        // Cutoffs have second precision, and tokens issued in the same second still pass
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        state.auth.update_account(&state.db, &session.user_id, PASSWORD, None, Some("New-Password-99")).await.unwrap();
+        assert_eq!(authenticate(&state, &session.access_token).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        let fresh = state.auth.login(&state.db, "user@example.com", "New-Password-99").await.unwrap();
+        assert!(authenticate(&state, &fresh.access_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejected_updates_change_nothing() {
+        let (state, session) = setup().await;
+
+        let error = state.auth
+            .update_account(&state.db, &session.user_id, PASSWORD, Some("new@example.com"), Some("weak"))
+            .await
+            .unwrap_err();
+        assert!(error.is::<AuthInputError>());
+
+        let user = state.db.get_user_by_id(&session.user_id).await.unwrap().unwrap();
+        assert_eq!(user.email, "user@example.com");
+        assert!(state.auth.login(&state.db, "user@example.com", PASSWORD).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn changed_emails_need_verifying_again() {
+        let (state, session) = setup().await;
+        // As when SMTP is configured; sending itself is skipped without SMTP_HOST
+        let auth = AuthService { verify_emails: true, ..state.auth.clone() };
+        require_verified(&state.db, &session.user_id).await.unwrap();
+
+        auth.update_account(&state.db, &session.user_id, PASSWORD, Some(" New@Example.com "), None).await.unwrap();
+
+        let user = state.db.get_user_by_id(&session.user_id).await.unwrap().unwrap();
+        assert_eq!((user.email.as_str(), user.email_verified), ("new@example.com", false));
+        let error = require_verified(&state.db, &session.user_id).await.unwrap_err();
+        assert_eq!(error.message(), "Verify your email address before starting scans");
+    }
+
+    #[tokio::test]
+    async fn emailed_tokens_verify_new_accounts_once() {
+        let (state, _) = setup().await;
+        let auth = AuthService { verify_emails: true, ..state.auth.clone() };
+        let session = auth.register(&state.db, "new@example.com", PASSWORD).await.unwrap();
+        assert!(!session.email_verified);
+        let error = require_verified(&state.db, &session.user_id).await.unwrap_err();
+        assert_eq!(error.message(), "Verify your email address before starting scans");
+
+        let error = auth.verify_email(&state.db, "unknown").await.unwrap_err();
+        assert_eq!(error.downcast::<AuthInputError>().unwrap().0, "Invalid verification token");
+        let expired = (chrono::Utc::now() - chrono::Duration::seconds(1)).timestamp();
+        state.db.create_email_verification(&hash_token("expired-token"), &session.user_id, expired).await.unwrap();
+        let error = auth.verify_email(&state.db, "expired-token").await.unwrap_err();
+        assert_eq!(error.downcast::<AuthInputError>().unwrap().0, "Verification token expired");
+        assert!(require_verified(&state.db, &session.user_id).await.is_err());
+
+        let expires_at = (chrono::Utc::now() + EMAIL_VERIFICATION_TTL).timestamp();
+        state.db.create_email_verification(&hash_token("verify-token"), &session.user_id, expires_at).await.unwrap();
+        auth.verify_email(&state.db, " verify-token ").await.unwrap();
+        require_verified(&state.db, &session.user_id).await.unwrap();
+        assert!(auth.login(&state.db, "new@example.com", PASSWORD).await.unwrap().email_verified);
+        // Tokens work once
+        assert!(auth.verify_email(&state.db, "verify-token").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn deleting_an_account_needs_its_password() {
+        let (state, session) = setup().await;
+
+        let error = state.auth.delete_account(&state.db, &session.user_id, "wrong").await.unwrap_err();
+        assert!(error.is::<IncorrectPassword>());
+        assert!(state.db.get_user_by_id(&session.user_id).await.unwrap().is_some());
+
+        state.auth.delete_account(&state.db, &session.user_id, PASSWORD).await.unwrap();
+        assert!(state.db.get_user_by_id(&session.user_id).await.unwrap().is_none());
+        assert_eq!(authenticate(&state, &session.access_token).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn reset_sets_the_password_and_ends_sessions() {
+        let (state, session) = setup().await;
+        let expires_at = (chrono::Utc::now() + PASSWORD_RESET_TTL).timestamp();
+        state.db.create_password_reset(&hash_token("reset-token"), &session.user_id, expires_at).await.unwrap();
+
+        let error = state.auth.reset_password(&state.db, "reset-token", "weak").await.unwrap_err();
+        assert!(error.is::<AuthInputError>());
+        state.auth.reset_password(&state.db, "reset-token", "New-Password-99").await.unwrap();
+
+        assert!(state.auth.login(&state.db, "user@example.com", PASSWORD).await.is_err());
+        assert!(state.auth.login(&state.db, "user@example.com", "New-Password-99").await.is_ok());
+        assert!(state.auth.refresh(&state.db, &session.refresh_token).await.is_err());
+        // Tokens work once
+        assert!(state.auth.reset_password(&state.db, "reset-token", "Other-Password-99").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn expired_reset_tokens_are_refused() {
+        let (state, session) = setup().await;
+        let expired = (chrono::Utc::now() - chrono::Duration::seconds(1)).timestamp();
+        state.db.create_password_reset(&hash_token("reset-token"), &session.user_id, expired).await.unwrap();
+
+        let error = state.auth.reset_password(&state.db, "reset-token", "New-Password-99").await.unwrap_err();
+        assert_eq!(error.downcast::<AuthInputError>().unwrap().0, "Reset token expired");
+        assert!(state.auth.login(&state.db, "user@example.com", PASSWORD).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reset_requests_dont_reveal_accounts() {
+        let (state, _) = setup().await;
+
+        // The same answer whether or not the account exists
+        assert!(state.auth.request_password_reset(&state.db, "user@example.com").await.is_ok());
+        assert!(state.auth.request_password_reset(&state.db, "nobody@example.com").await.is_ok());
+        assert!(state.auth.request_password_reset(&state.db, "not an email").await.is_ok());
+    }
+
+    #[test]
+    fn hash_costs_are_read_from_the_hash() {
+        assert_eq!(get_hash_cost("$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW"), Some(12));
+        assert_eq!(get_hash_cost("$2y$05$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW"), Some(5));
+        assert_eq!(get_hash_cost(&hash(PASSWORD, 6).unwrap()), Some(6));
+        for malformed in ["", "plaintext", "$2b$xx$abc", "$2b$"] {
+            assert_eq!(get_hash_cost(malformed), None, "{:?}", malformed);
+        }
+    }
+
+    #[tokio::test]
+    async fn new_hashes_use_the_configured_cost() {
+        let (state, session) = setup().await;
+        // Not bcrypt's default, so the setting was honoured
+        assert_ne!(MIN_BCRYPT_COST, DEFAULT_COST);
+        assert_eq!(bcrypt_cost(), MIN_BCRYPT_COST);
+        let user = state.db.get_user_by_id(&session.user_id).await.unwrap().unwrap();
+        assert_eq!(get_hash_cost(&user.password_hash), Some(MIN_BCRYPT_COST));
+    }
+
+    #[tokio::test]
+    async fn low_cost_hashes_are_upgraded_and_never_downgraded() {
+        let (state, session) = setup().await;
+        let user = state.db.get_user_by_id(&session.user_id).await.unwrap().unwrap();
+        let raised = MIN_BCRYPT_COST + 1;
+
+        // Only a wrong-cost hash is replaced, and then by one at the new cost
+        assert!(!upgrade_password_hash(&state.db, &user, PASSWORD, MIN_BCRYPT_COST).await.unwrap());
+        assert!(upgrade_password_hash(&state.db, &user, PASSWORD, raised).await.unwrap());
+        let upgraded = state.db.get_user_by_id(&session.user_id).await.unwrap().unwrap();
+        assert_eq!(get_hash_cost(&upgraded.password_hash), Some(raised));
+        assert!(verify(PASSWORD, &upgraded.password_hash).unwrap());
+        assert!(!upgrade_password_hash(&state.db, &upgraded, PASSWORD, raised).await.unwrap());
+
+        // Signing in at a lower configured cost never downgrades it
+        assert!(state.auth.login(&state.db, "user@example.com", PASSWORD).await.is_ok());
+        let after_login = state.db.get_user_by_id(&session.user_id).await.unwrap().unwrap();
+        assert_eq!(after_login.password_hash, upgraded.password_hash);
     }
 }
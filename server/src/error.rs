@@ -0,0 +1,96 @@
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::ApiResponse;
+
+/// A failed request, answered with its status and the usual `ApiResponse` body plus a
+/// machine-readable `code`, so clients can tell e.g. a bad url (`invalid_request`)
+/// from an outage (`internal_error`) without parsing `message`.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into(), retry_after: None }
+    }
+
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, "conflict", message)
+    }
+
+    /// 429 with a `Retry-After` header, for clients that keep failing to authenticate.
+    pub fn too_many_attempts(retry_after: Duration) -> Self {
+        let seconds = (retry_after.as_secs_f64().ceil() as u64).max(1);
+        Self {
+            retry_after: Some(Duration::from_secs(seconds)),
+            ..Self::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "too_many_attempts",
+                format!("Too many attempts, try again in {} seconds", seconds),
+            )
+        }
+    }
+
+    /// 423 with a `Retry-After` header, for an account locked after repeated wrong passwords.
+    pub fn account_locked(retry_after: Duration) -> Self {
+        let seconds = (retry_after.as_secs_f64().ceil() as u64).max(1);
+        Self {
+            retry_after: Some(Duration::from_secs(seconds)),
+            ..Self::new(
+                StatusCode::LOCKED,
+                "account_locked",
+                format!("Account locked after too many wrong passwords, try again in {} seconds", seconds),
+            )
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Logs `error` and answers 500 with `message`, keeping the details server-side.
+    pub fn internal(message: impl Into<String>, error: impl Display) -> Self {
+        let message = message.into();
+        tracing::error!("{}: {}", message, error);
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(ApiResponse::<()> {
+            success: false,
+            data: None,
+            message: Some(self.message),
+            code: Some(self.code),
+        });
+
+        match self.retry_after {
+            Some(retry_after) => (self.status, [(header::RETRY_AFTER, retry_after.as_secs().to_string())], body).into_response(),
+            None => (self.status, body).into_response(),
+        }
+    }
+}
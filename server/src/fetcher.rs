@@ -0,0 +1,456 @@
+use anyhow::{anyhow, Result};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::HeaderMap;
+use reqwest::{redirect, Client, Proxy, Response};
+use std::env;
+use std::error::Error as StdError;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const RETRY_BASE_DELAY_MS: u64 = 250;
+const MAX_REDIRECTS: usize = 10;
+
+/// Sent with every scanner request unless `SCAN_USER_AGENT` overrides it. The
+/// `KeyGuardBot` product token is what robots.txt groups are matched against.
+pub const DEFAULT_USER_AGENT: &str = concat!("KeyGuardBot/", env!("CARGO_PKG_VERSION"));
+
+/// HTTP access to scan targets, with timeouts so a slow host can't hold a scan task
+/// open forever and an optional allowlist of hosts the scan may touch.
+///
+/// Unless private targets are allowed, every fetch (pages, assets and each redirect
+/// hop) is refused when its host is or resolves to a private address. The check runs
+/// in the client's DNS resolver, so the address that is checked is the one connected to.
+pub struct Fetcher {
+    client: Client,
+    allowed_hosts: Vec<String>,
+    allow_private: bool,
+    credentials: Option<Credentials>,
+    user_agent: String,
+    /// Hostnames are resolved by the proxy, out of the resolver's reach.
+    proxied: bool,
+}
+
+/// Headers (login cookies, bearer tokens) that are only ever sent to one origin.
+#[derive(Clone)]
+pub struct Credentials {
+    pub origin: url::Origin,
+    pub headers: HeaderMap,
+}
+
+impl Fetcher {
+    /// An empty `allowed_hosts` lets the scan fetch from any host. `user_agent` overrides
+    /// `SCAN_USER_AGENT` for this fetcher.
+    pub fn new(
+        allowed_hosts: &[String],
+        allow_private: bool,
+        credentials: Option<Credentials>,
+        user_agent: Option<&str>,
+    ) -> Result<Self> {
+        let allowed_hosts: Vec<String> = allowed_hosts.iter().map(|host| host.trim().to_lowercase()).collect();
+
+        // Redirects are checked against the allowlist too, so a page can't bounce
+        // the scanner somewhere it was told not to go. Hostnames are vetted by the
+        // resolver; literal addresses never reach it, so they're checked here.
+        let redirect_hosts = allowed_hosts.clone();
+        // Headers stay on a redirected request, so credentials must not be redirected
+        // to another origin
+        let credentials_origin = credentials.as_ref().map(|credentials| credentials.origin.clone());
+        let policy = redirect::Policy::custom(move |attempt| {
+            let leaves_origin = attempt.previous().last().map(url::Url::origin) == credentials_origin
+                && credentials_origin.as_ref() != Some(&attempt.url().origin());
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if leaves_origin {
+                attempt.error("redirect to another origin while sending custom headers")
+            } else if !allow_private && is_private_host(attempt.url()) {
+                let blocked = BlockedAddress(attempt.url().host_str().unwrap_or_default().to_string());
+                attempt.error(blocked)
+            } else if !host_allowed(&redirect_hosts, attempt.url().host_str()) {
+                attempt.stop()
+            } else {
+                attempt.follow()
+            }
+        });
+
+        let user_agent = user_agent.map_or_else(self::user_agent, str::to_string);
+        let proxy = proxy()?;
+        let proxied = proxy.is_some();
+        let mut builder = Client::builder()
+            .user_agent(user_agent.clone())
+            .redirect(policy)
+            .connect_timeout(Duration::from_secs(env_u64("SCAN_CONNECT_TIMEOUT_SECS", DEFAULT_CONNECT_TIMEOUT_SECS)))
+            .timeout(Duration::from_secs(env_u64("SCAN_REQUEST_TIMEOUT_SECS", DEFAULT_REQUEST_TIMEOUT_SECS)));
+        if !allow_private {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(proxy);
+        }
+
+        Ok(Self { client: builder.build()?, allowed_hosts, allow_private, credentials, user_agent, proxied })
+    }
+
+    /// The User-Agent sent with every request, which robots.txt groups are matched on.
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    /// GETs `url`, retrying transient network failures (timeouts and failed connects)
+    /// with exponential backoff. HTTP error statuses are returned as-is.
+    pub async fn get(&self, url: &str) -> Result<Response> {
+        self.get_with_headers(url, HeaderMap::new()).await
+    }
+
+    /// Like `get`, sending `headers` as well, e.g. `If-None-Match` for a conditional GET.
+    pub async fn get_with_headers(&self, url: &str, headers: HeaderMap) -> Result<Response> {
+        let parsed = url::Url::parse(url).ok();
+        let host = parsed.as_ref().and_then(|u| u.host_str().map(str::to_string));
+        if !host_allowed(&self.allowed_hosts, host.as_deref()) {
+            return Err(anyhow!("{} is not in allowed_hosts", host.unwrap_or_else(|| url.to_string())));
+        }
+        if !self.allow_private && parsed.as_ref().is_none_or(is_private_host) {
+            return Err(BlockedAddress(host.unwrap_or_else(|| url.to_string())).into());
+        }
+        // Through a proxy the resolver never sees the hostname, so check it up front.
+        // Redirect targets aren't covered; the proxy has to police those.
+        if !self.allow_private && self.proxied {
+            if let Some(url::Host::Domain(domain)) = parsed.as_ref().and_then(url::Url::host) {
+                let _ = PublicResolver.resolve(domain.parse()?).await.map_err(|e| anyhow!(e))?;
+            }
+        }
+
+        let max_retries = env_u64("SCAN_MAX_RETRIES", DEFAULT_MAX_RETRIES as u64) as u32;
+        let mut attempt = 0;
+
+        let credentials = self.credentials.as_ref()
+            .filter(|credentials| parsed.as_ref().is_some_and(|u| u.origin() == credentials.origin));
+
+        loop {
+            let mut request = self.client.get(url).headers(headers.clone());
+            if let Some(credentials) = credentials {
+                request = request.headers(credentials.headers.clone());
+            }
+            match request.send().await {
+                Ok(response) => return Ok(response),
+                Err(e) if attempt < max_retries && is_transient(&e) => {
+                    tokio::time::sleep(Duration::from_millis(RETRY_BASE_DELAY_MS << attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Resolves hostnames like the system resolver does, but fails when any of the
+/// addresses is private, loopback or link-local.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if addrs.iter().any(|addr| is_private_ip(addr.ip())) {
+                return Err(BlockedAddress(name.as_str().to_string()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// A fetch refused because its host is, or resolves to, a private address.
+#[derive(Debug)]
+struct BlockedAddress(String);
+
+impl fmt::Display for BlockedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is a private network address", self.0)
+    }
+}
+
+impl StdError for BlockedAddress {}
+
+/// Whether scans may set `allow_private` on this server: `SCAN_ALLOW_PRIVATE=true`.
+/// Off by default, since anyone who can start a scan could otherwise reach internal
+/// services and cloud metadata endpoints through it.
+pub fn private_targets_allowed() -> bool {
+    env::var("SCAN_ALLOW_PRIVATE").is_ok_and(|value| matches!(value.trim().to_lowercase().as_str(), "true" | "1"))
+}
+
+/// A client for requests to other user-supplied urls, such as webhooks, that refuses
+/// hosts which are or resolve to private addresses, on every redirect hop as well.
+pub fn public_client_builder() -> reqwest::ClientBuilder {
+    let policy = redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if is_private_host(attempt.url()) {
+            let blocked = BlockedAddress(attempt.url().host_str().unwrap_or_default().to_string());
+            attempt.error(blocked)
+        } else {
+            attempt.follow()
+        }
+    });
+
+    Client::builder().redirect(policy).dns_resolver(Arc::new(PublicResolver))
+}
+
+pub fn user_agent() -> String {
+    env::var("SCAN_USER_AGENT").unwrap_or_else(|_| DEFAULT_USER_AGENT.to_string())
+}
+
+/// The proxy scan traffic goes through, from `SCAN_PROXY` (`http://`, `https://`,
+/// `socks5://` or `socks5h://`, optionally with credentials). Checked at startup.
+pub fn proxy() -> Result<Option<Proxy>> {
+    let Some(url) = env::var("SCAN_PROXY").ok().filter(|url| !url.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let parsed = url::Url::parse(url.trim()).map_err(|e| anyhow!("Invalid SCAN_PROXY: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(anyhow!("Invalid SCAN_PROXY: unsupported scheme {}", parsed.scheme()));
+    }
+    Ok(Some(Proxy::all(parsed.as_str())?))
+}
+
+/// Whether the url names `localhost` or a literal address that isn't publicly routable.
+/// Hostnames are not resolved here.
+pub fn is_private_host(url: &url::Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        Some(url::Host::Ipv4(ip)) => is_private_ip(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => is_private_ip(IpAddr::V6(ip)),
+        None => true,
+    }
+}
+
+/// Loopback, private (RFC 1918, unique local), link-local (which includes cloud
+/// metadata endpoints like 169.254.169.254), carrier-grade NAT, multicast, reserved and
+/// unspecified addresses, and NAT64 or 6to4 addresses embedding any of these.
+pub fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_private_ipv4(mapped),
+            None => is_private_ipv6(ip),
+        },
+    }
+}
+
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved, 240.0.0.0/4, including the broadcast address
+        || a >= 240
+}
+
+fn is_private_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // NAT64 (64:ff9b::/96) and 6to4 (2002::/16) addresses carry an IPv4 address that
+    // a gateway will forward to
+    let embedded = match segments {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] | [0x2002, high, low, ..] => {
+            Some(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))
+        }
+        _ => None,
+    };
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || (segments[0] & 0xfe00) == 0xfc00
+        || (segments[0] & 0xffc0) == 0xfe80
+        || embedded.is_some_and(is_private_ipv4)
+}
+
+/// Entries match the host exactly, or any subdomain when written as `*.example.com`.
+fn host_allowed(allowed_hosts: &[String], host: Option<&str>) -> bool {
+    if allowed_hosts.is_empty() {
+        return true;
+    }
+    let Some(host) = host.map(str::to_lowercase) else {
+        return false;
+    };
+
+    allowed_hosts.iter().any(|allowed| match allowed.strip_prefix("*.") {
+        Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+        None => host == *allowed,
+    })
+}
+
+fn is_transient(error: &reqwest::Error) -> bool {
+    (error.is_timeout() || error.is_connect()) && !is_blocked(error)
+}
+
+/// Whether the request failed because its host is a private address.
+pub fn is_blocked(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if error.is::<BlockedAddress>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+fn env_u64(key: &str, default: u64) -> u64 {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use std::future::IntoFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn blocks_metadata_endpoint() {
+        let fetcher = Fetcher::new(&[], false, None, None).unwrap();
+        let error = fetcher.get("http://169.254.169.254/latest/meta-data/").await.unwrap_err();
+        assert!(error.is::<BlockedAddress>(), "unexpected error: {}", error);
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_names_of_private_addresses() {
+        let resolved = PublicResolver.resolve("localhost".parse().unwrap()).await;
+        assert!(resolved.is_err());
+    }
+
+    #[tokio::test]
+    async fn slow_responses_time_out_and_are_retried() {
+        // No other test waits a second for a response
+        env::set_var("SCAN_REQUEST_TIMEOUT_SECS", "1");
+        let hits = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .route("/slow", get({
+                let hits = hits.clone();
+                move || async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+            }))
+            // Slow only the first time
+            .route("/flaky", get({
+                let hits = hits.clone();
+                move || async move {
+                    if hits.fetch_add(1, Ordering::SeqCst) == 0 {
+                        tokio::time::sleep(Duration::from_secs(10)).await;
+                    }
+                    "ok"
+                }
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(axum::serve(listener, router).into_future());
+        let fetcher = Fetcher::new(&[], true, None, None).unwrap();
+
+        let started = tokio::time::Instant::now();
+        let error = fetcher.get(&format!("{}/slow", base)).await.unwrap_err();
+        assert!(error.downcast_ref::<reqwest::Error>().is_some_and(reqwest::Error::is_timeout), "{}", error);
+        assert_eq!(hits.swap(0, Ordering::SeqCst), 1 + DEFAULT_MAX_RETRIES as usize);
+        assert!(started.elapsed() < Duration::from_secs(8));
+
+        let response = fetcher.get(&format!("{}/flaky", base)).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_configured_user_agent() {
+        let router = Router::new().route("/", get(|headers: axum::http::HeaderMap| async move {
+            headers.get("user-agent").and_then(|value| value.to_str().ok()).unwrap_or_default().to_string()
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(axum::serve(listener, router).into_future());
+
+        // SCAN_USER_AGENT is never set in tests
+        for (user_agent, expected) in [(None, DEFAULT_USER_AGENT), (Some("AcmeAudit/2.1 (+https://acme.example/bot)"), "AcmeAudit/2.1 (+https://acme.example/bot)")] {
+            let fetcher = Fetcher::new(&[], true, None, user_agent).unwrap();
+            assert_eq!(fetcher.user_agent(), expected);
+            assert_eq!(fetcher.get(&base).await.unwrap().text().await.unwrap(), expected);
+        }
+        assert!(DEFAULT_USER_AGENT.starts_with("KeyGuardBot/"));
+    }
+
+    #[test]
+    fn classifies_private_addresses() {
+        let cases = [
+            ("127.0.0.1", true),
+            ("10.1.2.3", true),
+            ("172.16.0.1", true),
+            ("192.168.1.1", true),
+            ("169.254.169.254", true),
+            ("100.64.0.1", true),
+            ("0.0.0.0", true),
+            ("192.0.0.8", true),
+            ("198.18.0.1", true),
+            ("198.19.255.254", true),
+            ("224.0.0.251", true),
+            ("239.255.255.250", true),
+            ("240.0.0.1", true),
+            ("255.255.255.255", true),
+            ("::1", true),
+            ("fd00::1", true),
+            ("fe80::1", true),
+            ("ff02::1", true),
+            ("::ffff:10.0.0.1", true),
+            ("64:ff9b::a9fe:a9fe", true),
+            ("64:ff9b::7f00:1", true),
+            ("2002:c0a8:101::1", true),
+            ("2002:a9fe:a9fe::", true),
+            ("93.184.216.34", false),
+            ("8.8.8.8", false),
+            ("192.0.1.1", false),
+            ("198.17.255.255", false),
+            ("198.20.0.1", false),
+            ("223.255.255.255", false),
+            ("2606:4700::1111", false),
+            ("64:ff9b::808:808", false),
+            ("2002:808:808::1", false),
+        ];
+
+        for (ip, private) in cases {
+            assert_eq!(is_private_ip(ip.parse().unwrap()), private, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn private_hosts() {
+        for url in ["http://localhost/", "http://app.localhost./", "http://[::1]:8080/", "http://192.168.0.10/"] {
+            assert!(is_private_host(&url::Url::parse(url).unwrap()), "{}", url);
+        }
+        assert!(!is_private_host(&url::Url::parse("https://example.com/").unwrap()));
+    }
+
+    #[test]
+    fn allowed_hosts_match_subdomains_only_with_wildcard() {
+        let allowed = vec!["example.com".to_string(), "*.cdn.net".to_string()];
+        assert!(host_allowed(&allowed, Some("example.com")));
+        assert!(!host_allowed(&allowed, Some("www.example.com")));
+        assert!(host_allowed(&allowed, Some("a.cdn.net")));
+        assert!(host_allowed(&allowed, Some("cdn.net")));
+        assert!(!host_allowed(&allowed, Some("evilcdn.net")));
+    }
+}
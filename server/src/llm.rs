@@ -0,0 +1,320 @@
+use anyhow::{anyhow, Result};
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+const DEFAULT_ROUTER_MODEL: &str = "gpt-4";
+const DEFAULT_ANTHROPIC_MODEL: &str = "claude-3-5-sonnet-latest";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+/// Anthropic accepts temperatures up to 1.0, where OpenAI-style APIs go up to 2.0.
+const MAX_ANTHROPIC_TEMPERATURE: f32 = 1.0;
+
+/// One prompt for a model, in a shape every backend can send.
+pub struct Completion {
+    pub model: String,
+    /// Routing hint for the AI router; other backends ignore it.
+    pub provider: Option<String>,
+    pub system: String,
+    pub prompt: String,
+    pub max_tokens: u32,
+    pub temperature: f32,
+}
+
+pub struct Answer {
+    pub text: String,
+    /// The model that actually answered, which routers may pick for us.
+    pub model: Option<String>,
+    pub usage: Option<TokenUsage>,
+}
+
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+/// A chat API `AIService` can send completions to. Backends only build requests and
+/// read answers; retries and fallbacks are the service's job.
+pub trait LlmBackend: Send + Sync {
+    /// The model used when the scan doesn't pick one.
+    fn default_model(&self) -> String;
+    fn request(&self, client: &Client, completion: &Completion) -> RequestBuilder;
+    /// Reads the answer out of a successful response body.
+    fn parse(&self, body: &str) -> Result<Answer>;
+}
+
+/// NEURA_ROUTER, or any API speaking OpenAI's `/chat/completions` schema.
+pub struct OpenAiBackend {
+    api_key: String,
+    base_url: String,
+}
+
+impl OpenAiBackend {
+    pub fn from_env() -> Self {
+        Self {
+            api_key: env::var("NEURA_ROUTER_API_KEY").unwrap_or_else(|_| "demo-key".to_string()),
+            base_url: env::var("NEURA_ROUTER_API_URL").unwrap_or_else(|_| "https://api.neura-router.com/v1".to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    /// Routing hint for the AI router; left out when no provider is chosen.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<&'a str>,
+    messages: [ChatMessage<'a>; 2],
+    max_tokens: u32,
+    temperature: f32,
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+    model: Option<String>,
+    usage: Option<ChatUsage>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatAnswer,
+}
+
+#[derive(Deserialize)]
+struct ChatAnswer {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ChatUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    total_tokens: u32,
+}
+
+impl LlmBackend for OpenAiBackend {
+    fn default_model(&self) -> String {
+        env::var("AI_MODEL")
+            .ok()
+            .filter(|model| !model.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_ROUTER_MODEL.to_string())
+    }
+
+    fn request(&self, client: &Client, completion: &Completion) -> RequestBuilder {
+        client
+            .post(format!("{}/chat/completions", self.base_url))
+            .bearer_auth(&self.api_key)
+            .json(&ChatRequest {
+                model: &completion.model,
+                provider: completion.provider.as_deref(),
+                messages: [
+                    ChatMessage { role: "system", content: &completion.system },
+                    ChatMessage { role: "user", content: &completion.prompt },
+                ],
+                max_tokens: completion.max_tokens,
+                temperature: completion.temperature,
+            })
+    }
+
+    fn parse(&self, body: &str) -> Result<Answer> {
+        let response: ChatResponse = serde_json::from_str(body)?;
+        let choice = response.choices.into_iter().next().ok_or_else(|| anyhow!("AI service answered without choices"))?;
+
+        Ok(Answer {
+            text: choice.message.content,
+            model: response.model,
+            usage: response.usage.map(|usage| TokenUsage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                total_tokens: usage.total_tokens,
+            }),
+        })
+    }
+}
+
+/// Anthropic's Messages API, used directly rather than through the router.
+pub struct AnthropicBackend {
+    api_key: String,
+    base_url: String,
+}
+
+impl AnthropicBackend {
+    /// `None` unless `ANTHROPIC_API_KEY` is set.
+    pub fn from_env() -> Option<Self> {
+        let api_key = env::var("ANTHROPIC_API_KEY").ok().filter(|key| !key.is_empty())?;
+        Some(Self {
+            api_key,
+            base_url: env::var("ANTHROPIC_API_URL").unwrap_or_else(|_| "https://api.anthropic.com".to_string()),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    system: &'a str,
+    messages: [ChatMessage<'a>; 1],
+    max_tokens: u32,
+    temperature: f32,
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    model: Option<String>,
+    usage: Option<MessagesUsage>,
+}
+
+/// Only `text` blocks carry the answer; others (e.g. `thinking`) have no `text`.
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MessagesUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+impl LlmBackend for AnthropicBackend {
+    fn default_model(&self) -> String {
+        env::var("ANTHROPIC_MODEL")
+            .ok()
+            .filter(|model| !model.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_ANTHROPIC_MODEL.to_string())
+    }
+
+    fn request(&self, client: &Client, completion: &Completion) -> RequestBuilder {
+        client
+            .post(format!("{}/v1/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&MessagesRequest {
+                model: &completion.model,
+                system: &completion.system,
+                messages: [ChatMessage { role: "user", content: &completion.prompt }],
+                max_tokens: completion.max_tokens,
+                temperature: completion.temperature.min(MAX_ANTHROPIC_TEMPERATURE),
+            })
+    }
+
+    fn parse(&self, body: &str) -> Result<Answer> {
+        let response: MessagesResponse = serde_json::from_str(body)?;
+        let text: String = response.content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .filter_map(|block| block.text)
+            .collect();
+        if text.is_empty() {
+            return Err(anyhow!("AI service answered without text"));
+        }
+
+        Ok(Answer {
+            text,
+            model: response.model,
+            usage: response.usage.map(|usage| TokenUsage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+                total_tokens: usage.input_tokens + usage.output_tokens,
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+impl OpenAiBackend {
+    /// A backend for a local stand-in of the API.
+    pub fn at(base_url: &str) -> Self {
+        Self { api_key: "test-key".to_string(), base_url: base_url.to_string() }
+    }
+}
+
+#[cfg(test)]
+impl AnthropicBackend {
+    /// A backend for a local stand-in of the API.
+    pub fn at(base_url: &str) -> Self {
+        Self { api_key: "test-key".to_string(), base_url: base_url.to_string() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn completion() -> Completion {
+        Completion {
+            model: "some-model".to_string(),
+            provider: Some("mistral".to_string()),
+            system: "Be brief.".to_string(),
+            prompt: "Two keys leaked.".to_string(),
+            max_tokens: 300,
+            temperature: 1.5,
+        }
+    }
+
+    /// The url, chosen headers and JSON body `backend` would send.
+    fn sent(backend: &dyn LlmBackend) -> (String, Vec<Option<String>>, Value) {
+        let request = backend.request(&Client::new(), &completion()).build().unwrap();
+        let headers = ["authorization", "x-api-key", "anthropic-version"]
+            .iter()
+            .map(|name| request.headers().get(*name).map(|value| value.to_str().unwrap().to_string()))
+            .collect();
+        let body = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        (request.url().to_string(), headers, body)
+    }
+
+    #[test]
+    fn each_backend_speaks_its_own_schema() {
+        let (url, headers, body) = sent(&OpenAiBackend::at("http://router.test/v1"));
+        assert_eq!(url, "http://router.test/v1/chat/completions");
+        assert_eq!(headers, [Some("Bearer test-key".to_string()), None, None]);
+        assert_eq!(body["provider"], "mistral");
+        assert_eq!(body["messages"], json!([{ "role": "system", "content": "Be brief." }, { "role": "user", "content": "Two keys leaked." }]));
+        assert_eq!(body["temperature"], 1.5);
+
+        let (url, headers, body) = sent(&AnthropicBackend::at("http://anthropic.test"));
+        assert_eq!(url, "http://anthropic.test/v1/messages");
+        assert_eq!(headers, [None, Some("test-key".to_string()), Some(ANTHROPIC_VERSION.to_string())]);
+        assert!(body.get("provider").is_none());
+        assert_eq!(body["system"], "Be brief.");
+        assert_eq!(body["messages"], json!([{ "role": "user", "content": "Two keys leaked." }]));
+        assert_eq!((body["model"].as_str(), body["max_tokens"].as_u64()), (Some("some-model"), Some(300)));
+        // Capped to what Anthropic accepts
+        assert_eq!(body["temperature"], 1.0);
+    }
+
+    #[test]
+    fn answers_are_read_from_either_schema() {
+        let chat = json!({
+            "model": "gpt-4-0613",
+            "choices": [{ "message": { "role": "assistant", "content": "Rotate it." } }],
+            "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 },
+        });
+        let answer = OpenAiBackend::at("").parse(&chat.to_string()).unwrap();
+        assert_eq!((answer.text.as_str(), answer.model.as_deref()), ("Rotate it.", Some("gpt-4-0613")));
+        assert_eq!(answer.usage.map(|usage| usage.total_tokens), Some(15));
+        assert!(OpenAiBackend::at("").parse(r#"{"choices": []}"#).is_err());
+
+        let messages = json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "content": [{ "type": "thinking", "thinking": "..." }, { "type": "text", "text": "Rotate " }, { "type": "text", "text": "it." }],
+            "usage": { "input_tokens": 12, "output_tokens": 4 },
+        });
+        let answer = AnthropicBackend::at("").parse(&messages.to_string()).unwrap();
+        assert_eq!(answer.text, "Rotate it.");
+        let usage = answer.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (12, 4, 16));
+        assert!(AnthropicBackend::at("").parse(r#"{"content": [{ "type": "thinking", "thinking": "..." }]}"#).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use axum::http::header;
+use axum::response::{Html, IntoResponse};
+use once_cell::sync::Lazy;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::*;
+
+/// The spec served at `/api/openapi.json`. Handlers are added under `paths` and the
+/// types they take or return under `components`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "KeyGuard API", description = "Scans websites for exposed API keys."),
+    paths(
+        health_check,
+        list_patterns,
+        start_scan,
+        batch_scan,
+        scan_text,
+        diff_scans,
+        get_scan_result,
+        delete_scan,
+        get_scan_findings,
+        update_finding_status,
+        get_scan_progress,
+        scan_events,
+        export_scan,
+        rescan,
+        cancel_scan,
+        register,
+        login,
+        refresh,
+        verify_email,
+        forgot_password,
+        reset_password,
+        logout,
+        list_api_keys,
+        create_api_key,
+        revoke_api_key,
+        get_user_scans,
+        get_user_stats,
+        list_schedules,
+        create_schedule,
+        delete_schedule,
+        list_false_positives,
+        clear_false_positives,
+        export_user_data,
+        delete_account,
+        get_profile,
+        update_profile,
+        get_all_scans,
+    ),
+    components(schemas(
+        HealthStatus,
+        BatchScanRequest,
+        TextScanRequest,
+        FindingPage,
+        FindingStatusUpdate,
+        AuthRequest,
+        AuthResponse,
+        RefreshRequest,
+        VerifyEmailRequest,
+        ForgotPasswordRequest,
+        ResetPasswordRequest,
+        CreateApiKeyRequest,
+        CreatedApiKey,
+        ScanPage,
+        CreateScheduleRequest,
+        DeleteAccountRequest,
+        UserProfile,
+        UpdateProfileRequest,
+        ScanRequest,
+        ScanResult,
+        ScanPlan,
+        ScanProgress,
+        ScanDiff,
+        TextScanResult,
+        PatternInfo,
+        ApiKeyFinding,
+        database::ApiKey,
+        UserStats,
+        ScheduledScan,
+        FalsePositive,
+        scanner::CustomPattern,
+        scanner::FetchedResource,
+        scanner::SecurityHeaders,
+        scanner::ScanSummary,
+        scanner::Severity,
+        scanner::ScanStatus,
+        scanner::FindingStatus,
+        ai_service::AiUsage,
+        database::ProviderCount,
+        database::DailyCount,
+        HealthResponse,
+        PatternsResponse,
+        MessageResponse,
+        ScanIdsResponse,
+        ScanResponse,
+        ScanPlanResponse,
+        TextScanResponse,
+        FindingPageResponse,
+        FindingResponse,
+        ScanDiffResponse,
+        ScanProgressResponse,
+        AuthResponseBody,
+        CreatedApiKeyResponse,
+        ApiKeysResponse,
+        ScanPageResponse,
+        UserStatsResponse,
+        ScheduleResponse,
+        SchedulesResponse,
+        FalsePositivesResponse,
+        CountResponse,
+        UserProfileResponse,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "scans", description = "Starting scans and reading their results"),
+        (name = "auth", description = "Accounts, sessions and API keys"),
+        (name = "user", description = "The signed-in user's scans, schedules and data"),
+        (name = "admin", description = "Instance-wide views for admins"),
+        (name = "meta", description = "Service status"),
+    )
+)]
+pub struct ApiDoc;
+
+/// `bearer` takes an access token from `/api/auth/login`; `api_key` a `kg_...` key,
+/// which is also accepted as a bearer token.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+        );
+        components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))));
+    }
+}
+
+/// Generated once; the spec can't change while the server runs.
+static SPEC: Lazy<String> = Lazy::new(|| ApiDoc::openapi().to_pretty_json().unwrap_or_default());
+
+pub async fn openapi_json() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], SPEC.as_str())
+}
+
+/// Swagger UI for the spec. The assets come from a CDN rather than being bundled, so
+/// the page needs internet access in the browser, but the server doesn't.
+pub async fn swagger_ui() -> Html<&'static str> {
+    Html(SWAGGER_UI_HTML)
+}
+
+const SWAGGER_UI_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>KeyGuard API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+    };
+  </script>
+</body>
+</html>
+"##;
@@ -0,0 +1,166 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_ATTEMPTS: usize = 5;
+const DEFAULT_WINDOW_SECS: u64 = 15 * 60;
+const DEFAULT_LOCKOUT_SECS: u64 = 60;
+/// Repeated lockouts double in length up to this.
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Counts failed authentication attempts per key (a client IP or an email address)
+/// in a sliding window. Reaching the limit locks the key out, for twice as long each
+/// time it happens again.
+#[derive(Clone)]
+pub struct AttemptLimiter {
+    attempts: Arc<Mutex<HashMap<String, Attempts>>>,
+    max_attempts: usize,
+    window: Duration,
+    lockout: Duration,
+}
+
+#[derive(Default)]
+struct Attempts {
+    failures: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+    lockouts: u32,
+}
+
+impl Attempts {
+    fn last_activity(&self) -> Option<Instant> {
+        self.failures.back().copied().max(self.locked_until)
+    }
+}
+
+impl Default for AttemptLimiter {
+    fn default() -> Self {
+        let var = |key: &str| env::var(key).ok().and_then(|value| value.parse::<u64>().ok()).filter(|&n| n > 0);
+
+        Self {
+            attempts: Arc::default(),
+            max_attempts: var("LOGIN_MAX_ATTEMPTS").map_or(DEFAULT_MAX_ATTEMPTS, |n| n as usize),
+            window: Duration::from_secs(var("LOGIN_WINDOW_SECS").unwrap_or(DEFAULT_WINDOW_SECS)),
+            lockout: Duration::from_secs(var("LOGIN_LOCKOUT_SECS").unwrap_or(DEFAULT_LOCKOUT_SECS)),
+        }
+    }
+}
+
+impl AttemptLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how long to wait when any of `keys` is locked out.
+    pub fn check(&self, keys: &[String]) -> Result<(), Duration> {
+        let now = Instant::now();
+        let attempts = self.attempts.lock().unwrap();
+        let retry_after = keys
+            .iter()
+            .filter_map(|key| attempts.get(key)?.locked_until)
+            .filter(|&until| until > now)
+            .map(|until| until - now)
+            .max();
+
+        match retry_after {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, keys: &[String]) {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap();
+
+        // Forget keys that have been quiet for longer than any lockout could last
+        let horizon = self.window + MAX_LOCKOUT;
+        attempts.retain(|_, entry| entry.last_activity().is_some_and(|last| now.duration_since(last) < horizon));
+
+        for key in keys {
+            let entry = attempts.entry(key.clone()).or_default();
+            while entry.failures.front().is_some_and(|&at| now.duration_since(at) > self.window) {
+                entry.failures.pop_front();
+            }
+            entry.failures.push_back(now);
+
+            if entry.failures.len() >= self.max_attempts {
+                let lockout = self.lockout.saturating_mul(1 << entry.lockouts.min(16)).min(MAX_LOCKOUT);
+                entry.locked_until = Some(now + lockout);
+                entry.lockouts += 1;
+                entry.failures.clear();
+            }
+        }
+    }
+
+    pub fn record_success(&self, keys: &[String]) {
+        let mut attempts = self.attempts.lock().unwrap();
+        for key in keys {
+            attempts.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_attempts: usize) -> AttemptLimiter {
+        AttemptLimiter {
+            attempts: Arc::default(),
+            max_attempts,
+            window: Duration::from_secs(60),
+            lockout: Duration::from_secs(60),
+        }
+    }
+
+    fn keys(email: &str) -> [String; 2] {
+        ["ip:192.0.2.1".to_string(), format!("email:{}", email)]
+    }
+
+    #[test]
+    fn repeated_failures_lock_out() {
+        let limiter = limiter(3);
+        let keys = keys("a@example.com");
+
+        limiter.record_failure(&keys);
+        limiter.record_failure(&keys);
+        assert!(limiter.check(&keys).is_ok());
+
+        limiter.record_failure(&keys);
+        let wait = limiter.check(&keys).unwrap_err();
+        assert!(wait > Duration::from_secs(55) && wait <= Duration::from_secs(60), "{:?}", wait);
+
+        // The IP is locked out for every account, and the account from every IP
+        assert!(limiter.check(&["ip:192.0.2.1".to_string()]).is_err());
+        assert!(limiter.check(&["ip:198.51.100.7".to_string(), "email:a@example.com".to_string()]).is_err());
+        assert!(limiter.check(&["ip:198.51.100.7".to_string(), "email:b@example.com".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn lockouts_double() {
+        let limiter = limiter(2);
+        let keys = keys("a@example.com");
+
+        for _ in 0..4 {
+            limiter.record_failure(&keys);
+        }
+        assert!(limiter.check(&keys).unwrap_err() > Duration::from_secs(60));
+    }
+
+    #[test]
+    fn success_resets() {
+        let limiter = limiter(3);
+        let keys = keys("a@example.com");
+
+        for _ in 0..3 {
+            limiter.record_failure(&keys);
+        }
+        limiter.record_success(&keys);
+        assert!(limiter.check(&keys).is_ok());
+
+        // The count starts over too
+        limiter.record_failure(&keys);
+        limiter.record_failure(&keys);
+        assert!(limiter.check(&keys).is_ok());
+    }
+}
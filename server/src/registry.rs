@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::scanner::ScanProgress;
+
+const PROGRESS_CHANNEL_CAPACITY: usize = 16;
+const DEFAULT_MAX_CONCURRENT_SCANS: usize = 4;
+
+/// Live, in-process state for scans that are currently running.
+#[derive(Clone)]
+pub struct ScanRegistry {
+    running: Arc<Mutex<HashMap<String, RunningScan>>>,
+    /// Caps how many scans fetch at once across the whole server; the rest wait.
+    scan_slots: Arc<Semaphore>,
+    /// Scan tasks, so shutdown can wait for them.
+    tasks: TaskTracker,
+    /// Serializes starting scans, so a duplicate check and the insert it guards can't interleave.
+    starting: Arc<tokio::sync::Mutex<()>>,
+}
+
+struct RunningScan {
+    progress: broadcast::Sender<ScanProgress>,
+    cancel: CancellationToken,
+}
+
+impl Default for ScanRegistry {
+    fn default() -> Self {
+        let max_concurrent_scans = env::var("MAX_CONCURRENT_SCANS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|&max| max > 0)
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_SCANS);
+
+        Self {
+            running: Arc::default(),
+            scan_slots: Arc::new(Semaphore::new(max_concurrent_scans)),
+            tasks: TaskTracker::new(),
+            starting: Arc::default(),
+        }
+    }
+}
+
+impl ScanRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the token the scan task should watch for cancellation.
+    pub fn register(&self, scan_id: &str) -> CancellationToken {
+        let (progress, _) = broadcast::channel(PROGRESS_CHANNEL_CAPACITY);
+        let cancel = CancellationToken::new();
+        self.running.lock().unwrap().insert(scan_id.to_string(), RunningScan { progress, cancel: cancel.clone() });
+        cancel
+    }
+
+    /// Returns `None` when the scan is not running in this process.
+    pub fn subscribe(&self, scan_id: &str) -> Option<broadcast::Receiver<ScanProgress>> {
+        self.running.lock().unwrap().get(scan_id).map(|scan| scan.progress.subscribe())
+    }
+
+    pub fn publish(&self, scan_id: &str, progress: &ScanProgress) {
+        if let Some(scan) = self.running.lock().unwrap().get(scan_id) {
+            // No subscribers is fine, nobody is watching this scan
+            let _ = scan.progress.send(progress.clone());
+        }
+    }
+
+    /// Asks a running scan to stop at its next checkpoint. Returns `false` when the
+    /// scan isn't running in this process.
+    pub fn cancel(&self, scan_id: &str) -> bool {
+        match self.running.lock().unwrap().get(scan_id) {
+            Some(scan) => {
+                scan.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Held while a scan is being started; see `scanner::start_scan`.
+    pub async fn lock_starts(&self) -> tokio::sync::OwnedMutexGuard<()> {
+        self.starting.clone().lock_owned().await
+    }
+
+    /// Waits until fewer than `MAX_CONCURRENT_SCANS` scans are running. The slot is
+    /// released when the permit is dropped.
+    pub async fn acquire_scan_slot(&self) -> OwnedSemaphorePermit {
+        self.scan_slots.clone().acquire_owned().await.expect("scan semaphore is never closed")
+    }
+
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+
+    /// Waits until every scan task has finished or `deadline` passes, and returns the
+    /// ids of scans still running.
+    pub async fn drain(&self, deadline: Instant) -> Vec<String> {
+        self.tasks.close();
+        let _ = tokio::time::timeout_at(deadline, self.tasks.wait()).await;
+        self.running.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Drops the scan's channel, which ends every subscriber's stream.
+    pub fn remove(&self, scan_id: &str) {
+        self.running.lock().unwrap().remove(scan_id);
+    }
+}
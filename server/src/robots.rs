@@ -0,0 +1,96 @@
+use regex::Regex;
+
+/// The rules of a robots.txt that apply to one user-agent.
+#[derive(Debug, Default)]
+pub struct Robots {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    allow: bool,
+    pattern: Regex,
+    specificity: usize,
+}
+
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+impl Robots {
+    /// Parses `content`, keeping the groups addressed to `user_agent`'s product token,
+    /// or the `*` groups when none name it.
+    pub fn parse(content: &str, user_agent: &str) -> Self {
+        let token = user_agent.split('/').next().unwrap_or(user_agent).trim().to_lowercase();
+
+        let mut groups: Vec<Group> = Vec::new();
+        let mut current = Group::default();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key.trim().to_lowercase().as_str() {
+                "user-agent" => {
+                    // A user-agent line after rules starts a new group
+                    if !current.rules.is_empty() {
+                        groups.push(std::mem::take(&mut current));
+                    }
+                    current.agents.push(value.to_lowercase());
+                }
+                "allow" | "disallow" if !current.agents.is_empty() => {
+                    // An empty Disallow allows everything, so it adds no rule
+                    if let Some(pattern) = compile_pattern(value) {
+                        current.rules.push(Rule {
+                            allow: key.trim().eq_ignore_ascii_case("allow"),
+                            pattern,
+                            specificity: value.len(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        groups.push(current);
+
+        let names_us = |group: &Group| group.agents.iter().any(|agent| agent != "*" && token.contains(agent.as_str()));
+        let wildcard = |group: &Group| group.agents.iter().any(|agent| agent == "*");
+        let selected: Vec<Group> = if groups.iter().any(names_us) {
+            groups.into_iter().filter(names_us).collect()
+        } else {
+            groups.into_iter().filter(wildcard).collect()
+        };
+
+        Self {
+            rules: selected.into_iter().flat_map(|group| group.rules).collect(),
+        }
+    }
+
+    /// Applies the most specific matching rule; `Allow` wins ties.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|rule| rule.pattern.is_match(path))
+            .max_by_key(|rule| (rule.specificity, rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+/// Turns a robots.txt path pattern (with `*` wildcards and an optional `$` end
+/// anchor) into an anchored regex.
+fn compile_pattern(pattern: &str) -> Option<Regex> {
+    if pattern.is_empty() {
+        return None;
+    }
+
+    let (body, anchored) = match pattern.strip_suffix('$') {
+        Some(body) => (body, true),
+        None => (pattern, false),
+    };
+    let escaped = body.split('*').map(regex::escape).collect::<Vec<_>>().join(".*");
+    Regex::new(&format!("^{}{}", escaped, if anchored { "$" } else { "" })).ok()
+}
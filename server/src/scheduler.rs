@@ -0,0 +1,141 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+use crate::database::{Database, ScheduledScan};
+use crate::registry::ScanRegistry;
+use crate::scanner;
+
+/// How often due schedules are looked for; runs start up to this late.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+pub const MIN_INTERVAL_HOURS: u32 = 1;
+/// 30 days.
+pub const MAX_INTERVAL_HOURS: u32 = 720;
+pub const MAX_SCHEDULES_PER_USER: u64 = 20;
+
+/// When a schedule created at `now` first runs: one interval later, to the second,
+/// since that's the precision `next_run` is stored with.
+pub fn first_run(now: DateTime<Utc>, interval_hours: u32) -> DateTime<Utc> {
+    let now = now.duration_trunc(Duration::seconds(1)).unwrap_or(now);
+    now + Duration::hours(interval_hours as i64)
+}
+
+/// The first run after `now` on the schedule's cadence. Runs missed while the server
+/// was down are skipped rather than caught up on one after another.
+pub fn next_run_after(due: DateTime<Utc>, interval_hours: u32, now: DateTime<Utc>) -> DateTime<Utc> {
+    let interval = Duration::hours(interval_hours.max(MIN_INTERVAL_HOURS) as i64);
+    if due > now {
+        return due;
+    }
+    let elapsed_intervals = (now - due).num_seconds() / interval.num_seconds() + 1;
+    due + interval * elapsed_intervals as i32
+}
+
+/// Polls for due schedules in the background and starts their scans.
+pub fn spawn_scheduler_task(db: Database, registry: ScanRegistry) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match run_due_schedules(&db, &registry, Utc::now()).await {
+                Ok(0) => {}
+                Ok(started) => tracing::info!("Started {} scheduled scans", started),
+                Err(e) => tracing::error!("Failed to run scheduled scans: {}", e),
+            }
+        }
+    });
+}
+
+/// Starts a scan for every schedule due at `now` and moves each on to its next run.
+/// Returns how many scans were started.
+pub async fn run_due_schedules(db: &Database, registry: &ScanRegistry, now: DateTime<Utc>) -> Result<usize> {
+    let mut started = 0;
+    for schedule in db.get_due_schedules(now).await? {
+        let next_run = next_run_after(schedule.next_run, schedule.interval_hours, now);
+        // Another instance sharing the database got to it first
+        if !db.claim_schedule_run(&schedule.id, schedule.next_run, next_run).await? {
+            continue;
+        }
+
+        let schedule_id = schedule.id.clone();
+        match start_scheduled_scan(db, registry, schedule).await {
+            Ok(scan_id) => {
+                db.set_schedule_last_scan(&schedule_id, &scan_id).await?;
+                started += 1;
+            }
+            Err(e) => tracing::error!("Scheduled scan {} failed to start: {}", schedule_id, e),
+        }
+    }
+    Ok(started)
+}
+
+async fn start_scheduled_scan(db: &Database, registry: &ScanRegistry, schedule: ScheduledScan) -> Result<String> {
+    let mut request = schedule.request;
+    request.user_id = Some(schedule.user_id);
+    // Validated when the schedule was created, but limits may have changed since
+    scanner::validate_request(&mut request).map_err(anyhow::Error::msg)?;
+
+    let scan = scanner::start_scan(db, registry, request).await?;
+    Ok(scan.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::ScanRequest;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn runs_keep_to_the_schedule_s_cadence() {
+        assert_eq!(first_run(at("2026-03-01T10:00:00.750Z"), 24), at("2026-03-02T10:00:00Z"));
+
+        let due = at("2026-03-01T10:00:00Z");
+        // Not due yet
+        assert_eq!(next_run_after(due, 24, at("2026-03-01T09:00:00Z")), due);
+        assert_eq!(next_run_after(due, 24, due), at("2026-03-02T10:00:00Z"));
+        assert_eq!(next_run_after(due, 24, at("2026-03-01T10:01:00Z")), at("2026-03-02T10:00:00Z"));
+        // Runs missed while the server was down are skipped
+        assert_eq!(next_run_after(due, 24, at("2026-03-04T12:00:00Z")), at("2026-03-05T10:00:00Z"));
+        assert_eq!(next_run_after(due, 0, at("2026-03-01T10:30:00Z")), at("2026-03-01T11:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn only_due_schedules_run_and_each_run_is_claimed_once() {
+        let db = Database::open(":memory:").await.unwrap();
+        let registry = ScanRegistry::new();
+        let user = db.create_user("user@example.com", "hash", "user", true).await.unwrap();
+        let now = at("2026-03-01T10:00:00Z");
+        // .invalid never resolves, so the scans end quickly
+        let request = ScanRequest { url: "https://keyguard-test.invalid/".to_string(), ..Default::default() };
+        let overdue = db.create_schedule(&user, &request, 6, now - Duration::hours(1)).await.unwrap();
+        let due = db.create_schedule(&user, &request, 24, now).await.unwrap();
+        let later = db.create_schedule(&user, &request, 24, now + Duration::seconds(1)).await.unwrap();
+
+        let due_ids: Vec<_> = db.get_due_schedules(now).await.unwrap().into_iter().map(|schedule| schedule.id).collect();
+        assert_eq!(due_ids, [overdue.id.clone(), due.id.clone()]);
+
+        assert_eq!(run_due_schedules(&db, &registry, now).await.unwrap(), 2);
+        // Already moved on, so running again at the same time starts nothing
+        assert_eq!(run_due_schedules(&db, &registry, now).await.unwrap(), 0);
+        registry.drain(tokio::time::Instant::now() + std::time::Duration::from_secs(10)).await;
+
+        let schedules = db.get_user_schedules(&user).await.unwrap();
+        let schedule = |id: &str| schedules.iter().find(|schedule| schedule.id == id).unwrap();
+        assert_eq!(schedule(&overdue.id).next_run, now + Duration::hours(5));
+        assert_eq!(schedule(&due.id).next_run, now + Duration::hours(24));
+        assert_eq!(schedule(&later.id).next_run, later.next_run);
+        assert!(schedule(&later.id).last_scan_id.is_none());
+        for id in [&overdue.id, &due.id] {
+            let scan_id = schedule(id).last_scan_id.clone().unwrap();
+            let scan = db.get_scan_result(&scan_id).await.unwrap().unwrap();
+            assert_eq!((scan.url.as_str(), scan.user_id.as_deref()), (request.url.as_str(), Some(user.as_str())));
+        }
+
+        // Another instance claiming a run first wins it
+        let next = now + Duration::hours(5);
+        assert!(db.claim_schedule_run(&overdue.id, next, next + Duration::hours(6)).await.unwrap());
+        assert!(!db.claim_schedule_run(&overdue.id, next, next + Duration::hours(6)).await.unwrap());
+    }
+}
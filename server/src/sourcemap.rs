@@ -0,0 +1,61 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct SourceMap {
+    #[serde(default)]
+    sources: Vec<Option<String>>,
+    #[serde(default, rename = "sourcesContent")]
+    sources_content: Vec<Option<String>>,
+}
+
+/// Where a script says its source map lives.
+pub enum MapReference {
+    /// The map is embedded in the script as a `data:` URL and already decoded.
+    Inline(String),
+    /// The map must be fetched from this absolute URL.
+    Remote(String),
+}
+
+/// Finds the script's last `//# sourceMappingURL=` comment (or the legacy `//@` form).
+pub fn find_reference(script: &str, script_url: &str) -> Option<MapReference> {
+    let start = script.rfind("//# sourceMappingURL=")
+        .or_else(|| script.rfind("//@ sourceMappingURL="))?;
+    let value = script[start + "//# sourceMappingURL=".len()..]
+        .split_whitespace()
+        .next()?;
+
+    if let Some(data) = value.strip_prefix("data:") {
+        return decode_data_url(data).map(MapReference::Inline);
+    }
+
+    let base = url::Url::parse(script_url).ok()?;
+    base.join(value).ok().map(|url| MapReference::Remote(url.to_string()))
+}
+
+/// Returns `(source name, original source)` for every source embedded in the map.
+/// Maps without `sourcesContent` only point at files and carry nothing to scan.
+pub fn embedded_sources(map: &str) -> Vec<(String, String)> {
+    let Ok(map) = serde_json::from_str::<SourceMap>(map) else {
+        return Vec::new();
+    };
+
+    map.sources_content
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, content)| {
+            let name = map.sources.get(i).cloned().flatten().unwrap_or_else(|| format!("source {}", i));
+            content.map(|content| (name, content))
+        })
+        .collect()
+}
+
+fn decode_data_url(data: &str) -> Option<String> {
+    let (meta, payload) = data.split_once(',')?;
+    if meta.ends_with(";base64") {
+        String::from_utf8(STANDARD.decode(payload).ok()?).ok()
+    } else {
+        percent_decode_str(payload).decode_utf8().ok().map(|decoded| decoded.into_owned())
+    }
+}
@@ -0,0 +1,151 @@
+use serde_json::Value;
+
+/// A JSON or `.env` file, parsed far enough to say where in it a key sits.
+pub enum Structure {
+    /// `(JSON path, value)` for every string in the document.
+    Json(Vec<(String, String)>),
+    /// The variable each line belongs to, indexed by line number - 1.
+    Env(Vec<Option<String>>),
+}
+
+impl Structure {
+    /// Parses `content` when its file name or content type says it's JSON or a `.env`
+    /// file. Returns `None` for other files and for content that doesn't parse.
+    pub fn parse(name: &str, content_type: Option<&str>, content: &str) -> Option<Structure> {
+        let file = file_name(name);
+        let is_json = file.ends_with(".json")
+            || content_type.is_some_and(|mime| mime == "application/json" || mime.ends_with("+json"));
+
+        if is_json {
+            let value: Value = serde_json::from_str(content).ok()?;
+            let mut strings = Vec::new();
+            collect_strings(&value, "$".to_string(), &mut strings);
+            Some(Structure::Json(strings))
+        } else if file == ".env" || file.starts_with(".env.") || file.ends_with(".env") {
+            env_variables(content).map(Structure::Env)
+        } else {
+            None
+        }
+    }
+
+    /// The JSON path or variable name holding `key`, which was found on `line`.
+    pub fn locate(&self, key: &str, line: u32) -> Option<&str> {
+        match self {
+            Structure::Json(strings) => {
+                // The key was matched in the raw text, where it may still be escaped
+                let key = serde_json::from_str::<String>(&format!("\"{}\"", key)).unwrap_or_else(|_| key.to_string());
+                strings.iter().find(|(_, value)| value.contains(&key)).map(|(path, _)| path.as_str())
+            }
+            Structure::Env(lines) => lines.get(line.checked_sub(1)? as usize)?.as_deref(),
+        }
+    }
+}
+
+/// The last path segment of a file name or URL, without query string or fragment.
+pub fn file_name(name: &str) -> &str {
+    let path = name.split(['?', '#']).next().unwrap_or(name);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn collect_strings(value: &Value, path: String, strings: &mut Vec<(String, String)>) {
+    match value {
+        Value::String(text) => strings.push((path, text.clone())),
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                collect_strings(item, format!("{}[{}]", path, index), strings);
+            }
+        }
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                let is_identifier = !name.is_empty()
+                    && !name.starts_with(|c: char| c.is_ascii_digit())
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+                let child = if is_identifier {
+                    format!("{}.{}", path, name)
+                } else {
+                    format!("{}[{}]", path, serde_json::to_string(name).unwrap_or_default())
+                };
+                collect_strings(field, child, strings);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Maps each line of `KEY=value` text to its variable. Blank lines and `#` comments
+/// belong to none; quoted values may span lines. Any other line means it isn't a
+/// `.env` file after all.
+fn env_variables(content: &str) -> Option<Vec<Option<String>>> {
+    let mut lines = Vec::new();
+    // The variable whose quoted value is still open, and its quote character
+    let mut open: Option<(String, char)> = None;
+
+    for line in content.lines() {
+        if let Some((name, quote)) = &open {
+            lines.push(Some(name.clone()));
+            if line.contains(*quote) {
+                open = None;
+            }
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            lines.push(None);
+            continue;
+        }
+
+        let (name, value) = trimmed.strip_prefix("export ").unwrap_or(trimmed).split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            return None;
+        }
+        let value = value.trim_start();
+        if let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            if !value[1..].contains(quote) {
+                open = Some((name.to_string(), quote));
+            }
+        }
+        lines.push(Some(name.to_string()));
+    }
+
+    Some(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_strings_are_located_by_path() {
+        let content = r#"{"services": {"stripe": {"key": "sk_live_a\/b"}}, "hosts": ["a", "sk_live_c"], "x-api-key": "k1"}"#;
+        let structure = Structure::parse("https://cdn.example.com/config.json?v=3", None, content).unwrap();
+        // Keys are matched in the raw text, escapes included
+        assert_eq!(structure.locate(r"sk_live_a\/b", 1), Some("$.services.stripe.key"));
+        assert_eq!(structure.locate("sk_live_c", 1), Some("$.hosts[1]"));
+        assert_eq!(structure.locate("k1", 1), Some(r#"$["x-api-key"]"#));
+        assert_eq!(structure.locate("missing", 1), None);
+
+        assert!(Structure::parse("settings", Some("application/manifest+json"), "{}").is_some());
+        assert!(Structure::parse("config.json", None, "{ not json").is_none());
+        assert!(Structure::parse("app.js", Some("application/javascript"), "{}").is_none());
+    }
+
+    #[test]
+    fn env_lines_are_located_by_variable() {
+        let content = "# Stripe\nexport STRIPE_KEY=sk_live_1\n\nPRIVATE_KEY=\"-----BEGIN\nbody\n-----END\"\nAWS_KEY = AKIA\n";
+        let structure = Structure::parse("deploy/.env.production", None, content).unwrap();
+        let variables: Vec<_> = (1..=8).map(|line| structure.locate("", line)).collect();
+        assert_eq!(
+            variables,
+            [None, Some("STRIPE_KEY"), None, Some("PRIVATE_KEY"), Some("PRIVATE_KEY"), Some("PRIVATE_KEY"), Some("AWS_KEY"), None],
+        );
+        assert_eq!(structure.locate("", 0), None);
+
+        assert!(Structure::parse("settings.env", None, "A=1").is_some());
+        // Anything but assignments, comments and blank lines means it isn't an env file
+        assert!(Structure::parse(".env", None, "A=1\nconsole.log(a)\n").is_none());
+        assert!(Structure::parse(".env", None, "MY KEY=1").is_none());
+        assert!(Structure::parse("env.txt", None, "A=1").is_none());
+    }
+}
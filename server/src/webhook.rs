@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use reqwest::{header, Client};
+use sha2::Sha256;
+use std::env;
+use std::time::Duration;
+
+use crate::fetcher;
+use crate::scanner::ScanResult;
+
+const SIGNATURE_HEADER: &str = "X-KeyGuard-Signature";
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RETRIES: u32 = 2;
+const RETRY_BASE_DELAY_MS: u64 = 500;
+
+/// POSTs the finished scan to `url`. When `WEBHOOK_SECRET` is set the body is signed
+/// as `X-KeyGuard-Signature: sha256=<hex HMAC-SHA256>` so receivers can verify it came
+/// from us. Network errors and 5xx responses are retried.
+///
+/// Unless `allow_private`, as for the scan itself, receivers that are or resolve to
+/// private addresses are refused, so a webhook can't be aimed at internal services.
+pub async fn deliver(url: &str, result: &ScanResult, allow_private: bool) -> Result<()> {
+    let body = serde_json::to_vec(result)?;
+    let signature = env::var("WEBHOOK_SECRET").ok().map(|secret| sign(&secret, &body));
+    post(url, &body, signature.as_deref(), allow_private).await
+}
+
+async fn post(url: &str, body: &[u8], signature: Option<&str>, allow_private: bool) -> Result<()> {
+    // Literal addresses never reach the resolver, so they're checked here
+    if !allow_private && url::Url::parse(url).as_ref().map_or(true, fetcher::is_private_host) {
+        return Err(anyhow!("webhook {} is a private network address", url));
+    }
+    let builder = if allow_private { Client::builder() } else { fetcher::public_client_builder() };
+    let client = builder
+        .user_agent(fetcher::user_agent())
+        .timeout(DELIVERY_TIMEOUT)
+        .build()?;
+
+    let mut attempt = 0;
+    loop {
+        let mut request = client
+            .post(url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+        if let Some(signature) = signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) if !response.status().is_server_error() => {
+                return Err(anyhow!("webhook {} answered {}", url, response.status()));
+            }
+            Ok(response) => anyhow!("webhook {} answered {}", url, response.status()),
+            Err(e) if fetcher::is_blocked(&e) => return Err(e.into()),
+            Err(e) => e.into(),
+        };
+
+        if attempt >= MAX_RETRIES {
+            return Err(error);
+        }
+        tokio::time::sleep(Duration::from_millis(RETRY_BASE_DELAY_MS << attempt)).await;
+        attempt += 1;
+    }
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post as route_post;
+    use axum::Router;
+    use std::sync::{Arc, Mutex};
+
+    /// A webhook receiver on a local port that answers with `statuses` in turn (200 once
+    /// they run out) and records each delivery's signature header and body.
+    async fn receiver(statuses: Vec<StatusCode>) -> (String, Arc<Mutex<Vec<(Option<String>, Vec<u8>)>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(statuses));
+        let log = received.clone();
+        let app = Router::new().route(
+            "/hook",
+            route_post(move |headers: HeaderMap, body: axum::body::Bytes| {
+                let log = log.clone();
+                let statuses = statuses.clone();
+                async move {
+                    let signature = headers.get(SIGNATURE_HEADER).and_then(|v| v.to_str().ok()).map(str::to_string);
+                    log.lock().unwrap().push((signature, body.to_vec()));
+                    let mut statuses = statuses.lock().unwrap();
+                    if statuses.is_empty() { StatusCode::OK } else { statuses.remove(0) }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_body() {
+        let (url, received) = receiver(vec![]).await;
+        let body = br#"{"id":"scan"}"#;
+        let signature = sign("secret", body);
+
+        post(&url, body, Some(&signature), true).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.as_deref(), Some(signature.as_str()));
+        assert_eq!(received[0].1, body);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_but_not_client_errors() {
+        let (url, received) = receiver(vec![StatusCode::INTERNAL_SERVER_ERROR, StatusCode::BAD_GATEWAY]).await;
+        post(&url, b"{}", None, true).await.unwrap();
+        assert_eq!(received.lock().unwrap().len(), 3);
+
+        let (url, received) = receiver(vec![StatusCode::NOT_FOUND]).await;
+        assert!(post(&url, b"{}", None, true).await.is_err());
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_private_receivers() {
+        let (url, received) = receiver(vec![]).await;
+        assert!(post(&url, b"{}", None, false).await.is_err());
+        assert!(post("http://169.254.169.254/latest/meta-data/", b"{}", None, false).await.is_err());
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn signature_is_hex_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
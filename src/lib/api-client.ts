@@ -3,11 +3,33 @@ interface ApiResponse<T> {
   success: boolean;
   data?: T;
   message?: string;
+  code?: string;
+}
+
+/** A failed request; `code` is the server's machine-readable error code, e.g. `invalid_request`. */
+export class ApiError extends Error {
+  constructor(message: string, public status: number, public code?: string) {
+    super(message);
+    this.name = 'ApiError';
+  }
 }
 
 interface ScanRequest {
   url: string;
-  user_id?: string;
+  /** Emailed a summary when the scan finishes; requires being signed in. */
+  notify_email?: string;
+  /** Start a new scan even when one of this url is already running. */
+  force?: boolean;
+  /** Severity to report per provider or key type, e.g. `{ "GitHub": "critical" }`. */
+  severity_overrides?: Record<string, 'critical' | 'high' | 'medium' | 'low'>;
+}
+
+export interface AiUsage {
+  model: string;
+  prompt_tokens: number;
+  completion_tokens: number;
+  total_tokens: number;
+  estimated_cost_usd: number | null;
 }
 
 interface ScanResult {
@@ -21,6 +43,14 @@ interface ScanResult {
   total_checks: number;
   completed_checks: number;
   ai_recommendations?: string;
+  error?: string | null;
+  parent_scan_id?: string | null;
+  ai_usage?: AiUsage | null;
+  final_url?: string | null;
+  fetched_resources?: FetchedResource[];
+  security_headers?: SecurityHeaders | null;
+  /** Set when an already running scan of the url was returned instead of a new one. */
+  deduplicated?: boolean;
   summary: {
     critical: number;
     high: number;
@@ -30,23 +60,157 @@ interface ScanResult {
   };
 }
 
+export interface FetchedResource {
+  url: string;
+  kind: 'page' | 'script' | 'stylesheet' | 'source_map';
+  status: number | null;
+  size: number | null;
+  content_type: string | null;
+  scanned: boolean;
+  error: string | null;
+  etag?: string;
+  last_modified?: string;
+  /** `value_hash` of each key found in the asset. */
+  key_hashes?: string[];
+  source_map?: string;
+  /** Not modified since the scan this one reran; its findings were carried over. */
+  unchanged?: boolean;
+}
+
+export interface SecurityHeaders {
+  strict_transport_security: boolean;
+  content_security_policy: boolean;
+  x_content_type_options: boolean;
+  /** An https page loading scripts or stylesheets over http. */
+  mixed_content: boolean;
+  insecure_assets: string[];
+}
+
+export interface ScanPlan {
+  url: string;
+  final_url: string | null;
+  pages: string[];
+  scripts: string[];
+  stylesheets: string[];
+}
+
+interface ScanPage {
+  scans: ScanResult[];
+  total: number;
+  limit: number;
+  offset: number;
+}
+
+export interface FindingPage {
+  findings: ApiKeyFinding[];
+  total: number;
+  limit: number;
+  offset: number;
+}
+
+export interface FindingFilters {
+  severity?: ApiKeyFinding['severity'];
+  provider?: string;
+  min_confidence?: number;
+  status?: FindingStatus;
+  limit?: number;
+  offset?: number;
+}
+
 interface ScanProgress {
   stage: string;
   progress: number;
   message: string;
+  /** Estimated seconds left; null until known and once the scan has ended. */
+  eta_seconds: number | null;
 }
 
 interface ApiKeyFinding {
   id: string;
   key_type: string;
+  /** e.g. `AWS` or `Stripe`; `Custom` for custom patterns. */
+  provider: string;
   value: string;
+  value_hash?: string;
   location: string;
   severity: 'critical' | 'high' | 'medium' | 'low';
   description: string;
   recommendation?: string;
   context: string;
   line_number?: number;
+  column?: number | null;
   confidence: number;
+  locations?: string[];
+  occurrences?: number;
+  live?: boolean | null;
+  status: FindingStatus;
+  status_note?: string | null;
+  status_updated_at?: string | null;
+}
+
+export type FindingStatus = 'open' | 'resolved' | 'false_positive' | 'accepted';
+
+export interface DetectionPattern {
+  name: string;
+  provider: string;
+  severity: 'critical' | 'high' | 'medium' | 'low';
+  description: string;
+  regex?: string;
+}
+
+export interface UserProfile {
+  id: string;
+  email: string;
+  role: 'user' | 'admin';
+  created_at: string;
+  verified: boolean;
+}
+
+export interface UserStats {
+  total_scans: number;
+  findings: ScanResult['summary'];
+  top_providers: { provider: string; findings: number }[];
+  daily_scans: { date: string; scans: number }[];
+}
+
+export interface FalsePositive {
+  value_hash: string;
+  key_type: string;
+  masked_value: string;
+  scan_id: string;
+  created_at: string;
+}
+
+export interface ScheduledScan {
+  id: string;
+  url: string;
+  interval_hours: number;
+  next_run: string;
+  last_run_at: string | null;
+  last_scan_id: string | null;
+  created_at: string;
+}
+
+export interface AuthSession {
+  token: string;
+  refresh_token: string;
+  expires_in: number;
+  user_id: string;
+  email_verified: boolean;
+}
+
+export interface ApiKey {
+  id: string;
+  name: string;
+  prefix: string;
+  created_at: string;
+  last_used_at: string | null;
+}
+
+export interface HealthStatus {
+  status: 'ok' | 'unhealthy';
+  database: string;
+  ai: 'configured' | 'not_configured';
 }
 
 class APIClient {
@@ -78,7 +242,9 @@ class APIClient {
     });
 
     if (!response.ok) {
-      throw new Error(`API request failed: ${response.statusText}`);
+      // Validation errors carry a message meant for the user
+      const body = await response.json().catch(() => null);
+      throw new ApiError(body?.message || `API request failed: ${response.statusText}`, response.status, body?.code);
     }
 
     return response.json();
@@ -107,6 +273,34 @@ class APIClient {
     return response.data;
   }
 
+  async getScanFindings(scanId: string, filters: FindingFilters = {}): Promise<FindingPage> {
+    const query = new URLSearchParams(
+      Object.entries(filters)
+        .filter(([, value]) => value !== undefined)
+        .map(([name, value]) => [name, String(value)]),
+    );
+    const response = await this.request<FindingPage>(`/api/scan/${scanId}/findings?${query}`);
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to get findings');
+    }
+
+    return response.data;
+  }
+
+  async updateFindingStatus(scanId: string, findingId: string, status: FindingStatus, note?: string): Promise<ApiKeyFinding> {
+    const response = await this.request<ApiKeyFinding>(`/api/scan/${scanId}/findings/${findingId}`, {
+      method: 'PATCH',
+      body: JSON.stringify({ status, note }),
+    });
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to update finding');
+    }
+
+    return response.data;
+  }
+
   async getScanProgress(scanId: string): Promise<ScanProgress> {
     const response = await this.request<ScanProgress>(`/api/scan/${scanId}/progress`);
     
@@ -117,8 +311,18 @@ class APIClient {
     return response.data;
   }
 
-  async register(email: string, password: string): Promise<{ token: string; user_id: string }> {
-    const response = await this.request<{ token: string; user_id: string }>('/api/auth/register', {
+  async getPatterns(): Promise<DetectionPattern[]> {
+    const response = await this.request<DetectionPattern[]>('/api/patterns');
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to get detection patterns');
+    }
+
+    return response.data;
+  }
+
+  async register(email: string, password: string): Promise<AuthSession> {
+    const response = await this.request<AuthSession>('/api/auth/register', {
       method: 'POST',
       body: JSON.stringify({ email, password }),
     });
@@ -130,8 +334,8 @@ class APIClient {
     return response.data;
   }
 
-  async login(email: string, password: string): Promise<{ token: string; user_id: string }> {
-    const response = await this.request<{ token: string; user_id: string }>('/api/auth/login', {
+  async login(email: string, password: string): Promise<AuthSession> {
+    const response = await this.request<AuthSession>('/api/auth/login', {
       method: 'POST',
       body: JSON.stringify({ email, password }),
     });
@@ -143,8 +347,86 @@ class APIClient {
     return response.data;
   }
 
-  async getUserScans(): Promise<ScanResult[]> {
-    const response = await this.request<ScanResult[]>('/api/user/scans');
+  async refresh(refreshToken: string): Promise<AuthSession> {
+    const response = await this.request<AuthSession>('/api/auth/refresh', {
+      method: 'POST',
+      body: JSON.stringify({ refresh_token: refreshToken }),
+    });
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Session expired');
+    }
+
+    this.token = response.data.token;
+    return response.data;
+  }
+
+  async verifyEmail(token: string): Promise<void> {
+    const response = await this.request<string>('/api/auth/verify', {
+      method: 'POST',
+      body: JSON.stringify({ token }),
+    });
+
+    if (!response.success) {
+      throw new Error(response.message || 'Verification failed');
+    }
+  }
+
+  async forgotPassword(email: string): Promise<void> {
+    await this.request<string>('/api/auth/forgot-password', {
+      method: 'POST',
+      body: JSON.stringify({ email }),
+    });
+  }
+
+  async resetPassword(token: string, newPassword: string): Promise<void> {
+    const response = await this.request<string>('/api/auth/reset-password', {
+      method: 'POST',
+      body: JSON.stringify({ token, new_password: newPassword }),
+    });
+
+    if (!response.success) {
+      throw new Error(response.message || 'Password reset failed');
+    }
+  }
+
+  async logout(refreshToken?: string): Promise<void> {
+    await this.request<string>('/api/auth/logout', {
+      method: 'POST',
+      body: refreshToken ? JSON.stringify({ refresh_token: refreshToken }) : undefined,
+    });
+    this.token = undefined;
+  }
+
+  async createApiKey(name: string): Promise<ApiKey & { key: string }> {
+    const response = await this.request<ApiKey & { key: string }>('/api/auth/api-keys', {
+      method: 'POST',
+      body: JSON.stringify({ name }),
+    });
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to create API key');
+    }
+
+    return response.data;
+  }
+
+  async listApiKeys(): Promise<ApiKey[]> {
+    const response = await this.request<ApiKey[]>('/api/auth/api-keys');
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to list API keys');
+    }
+
+    return response.data;
+  }
+
+  async revokeApiKey(id: string): Promise<void> {
+    await this.request<string>(`/api/auth/api-keys/${id}`, { method: 'DELETE' });
+  }
+
+  async getUserScans(limit = 20, offset = 0): Promise<ScanPage> {
+    const response = await this.request<ScanPage>(`/api/user/scans?limit=${limit}&offset=${offset}`);
     
     if (!response.success || !response.data) {
       throw new Error(response.message || 'Failed to get user scans');
@@ -153,8 +435,85 @@ class APIClient {
     return response.data;
   }
 
-  async healthCheck(): Promise<string> {
-    const response = await this.request<string>('/api/health');
+  async getProfile(): Promise<UserProfile> {
+    const response = await this.request<UserProfile>('/api/user/me');
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to get profile');
+    }
+
+    return response.data;
+  }
+
+  async updateProfile(currentPassword: string, changes: { email?: string; new_password?: string }): Promise<UserProfile> {
+    const response = await this.request<UserProfile>('/api/user/me', {
+      method: 'PATCH',
+      body: JSON.stringify({ current_password: currentPassword, ...changes }),
+    });
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to update profile');
+    }
+
+    return response.data;
+  }
+
+  async getUserStats(): Promise<UserStats> {
+    const response = await this.request<UserStats>('/api/user/stats');
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to get stats');
+    }
+
+    return response.data;
+  }
+
+  async createSchedule(request: ScanRequest, intervalHours: number): Promise<ScheduledScan> {
+    const response = await this.request<ScheduledScan>('/api/user/schedules', {
+      method: 'POST',
+      body: JSON.stringify({ ...request, interval_hours: intervalHours }),
+    });
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to create schedule');
+    }
+
+    return response.data;
+  }
+
+  async listSchedules(): Promise<ScheduledScan[]> {
+    const response = await this.request<ScheduledScan[]>('/api/user/schedules');
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to list schedules');
+    }
+
+    return response.data;
+  }
+
+  async deleteSchedule(id: string): Promise<void> {
+    await this.request<string>(`/api/user/schedules/${id}`, { method: 'DELETE' });
+  }
+
+  async listFalsePositives(): Promise<FalsePositive[]> {
+    const response = await this.request<FalsePositive[]>('/api/user/false-positives');
+
+    if (!response.success || !response.data) {
+      throw new Error(response.message || 'Failed to list false positives');
+    }
+
+    return response.data;
+  }
+
+  /** Returns how many false positives were forgotten. */
+  async clearFalsePositives(): Promise<number> {
+    const response = await this.request<number>('/api/user/false-positives', { method: 'DELETE' });
+
+    return response.data ?? 0;
+  }
+
+  async healthCheck(): Promise<HealthStatus> {
+    const response = await this.request<HealthStatus>('/api/health');
     
     if (!response.success || !response.data) {
       throw new Error('Health check failed');
@@ -165,4 +524,4 @@ class APIClient {
 }
 
 export const apiClient = new APIClient();
-export type { ScanResult, ScanProgress, ApiKeyFinding, ScanRequest };
+export type { ScanResult, ScanProgress, ApiKeyFinding, ScanRequest, ScanPage };
@@ -4,7 +4,6 @@ import { analytics } from './analytics';
 
 export interface ScanOptions {
   url: string;
-  userId?: string;
 }
 
 export class ScannerClient {
@@ -12,10 +11,7 @@ export class ScannerClient {
     analytics.trackScanStarted(options.url);
     
     try {
-      const result = await apiClient.startScan({
-        url: options.url,
-        user_id: options.userId,
-      });
+      const result = await apiClient.startScan({ url: options.url });
       
       console.log('Scan started:', result);
       return result;